@@ -0,0 +1,61 @@
+// LBFluidSim::update for every kernel on a periodic channel between two walls, driven by a body
+// force, with square blocks filling a share of the cells. Throughput is in lattice cells, so the
+// Melem/s criterion reports are MLUPS.
+//
+//     cargo bench --no-default-features --bench update
+//     cargo bench --no-default-features --bench update -- 1024x512/AaPattern
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fluid::{BoundaryConfig, EdgeBoundary, GridStats, Kernel, LBFluidSim, ObstacleBoard, Tup2};
+
+const SHAPES: [(usize, usize); 3] = [(256, 128), (512, 256), (1024, 512)];
+// share of the cells covered by obstacles, near enough with whole blocks
+const OBSTACLE_DENSITIES: [f32; 3] = [0., 0.1, 0.3];
+// one block in the middle of every square of this many cells, the shapes are multiples of it
+const PITCH: usize = 16;
+
+fn porous_channel(shape: (usize, usize), obstacle_density: f32, kernel: Kernel) -> LBFluidSim {
+    let side = (PITCH as f32 * obstacle_density.sqrt()).round() as usize;
+    let in_block = |k: usize| (k % PITCH).abs_diff(PITCH / 2) * 2 < side;
+    let blocks = (0..shape.0)
+        .flat_map(|i| (0..shape.1).map(move |j| (i, j)))
+        .filter(|(i, j)| in_block(*i) && in_block(*j))
+        .collect();
+    let board = ObstacleBoard::new(GridStats::new(shape, 1.), blocks);
+    let boundaries = BoundaryConfig::new(
+        EdgeBoundary::Periodic,
+        EdgeBoundary::Periodic,
+        EdgeBoundary::Wall,
+        EdgeBoundary::Wall,
+    );
+    let mut fluid_sim = LBFluidSim::new(board, |_| 1., |_| Tup2(0., 0.), 0.1, boundaries);
+    fluid_sim.set_uniform_force(Tup2(1e-7, 0.));
+    fluid_sim.set_kernel(kernel);
+    // past the first update, which may collide once more to reach the phase of the kernel
+    for _ in 0..2 {
+        fluid_sim.update();
+    }
+    fluid_sim
+}
+
+fn update(c: &mut Criterion) {
+    for shape in SHAPES {
+        let mut group = c.benchmark_group(format!("update/{}x{}", shape.0, shape.1));
+        group.throughput(Throughput::Elements((shape.0 * shape.1) as u64));
+        group.sample_size(10);
+        for kernel in Kernel::ALL {
+            for obstacle_density in OBSTACLE_DENSITIES {
+                let mut fluid_sim = porous_channel(shape, obstacle_density, kernel);
+                let parameter = format!("obstacles-{}%", (obstacle_density * 100.).round());
+                let id = BenchmarkId::new(format!("{:?}", kernel), parameter);
+                group.bench_function(id, |b| {
+                    b.iter(|| fluid_sim.update())
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, update);
+criterion_main!(benches);
@@ -0,0 +1,248 @@
+// Runs a simulation without a window and writes the fields to disk, for machines with no display.
+//
+//     fluid-batch --scenario cylinder --shape 400x100 --steps 20000 --viscosity 0.02 --out runs/cyl
+//     fluid-batch --scenario scenarios/cylinder.toml --out runs/cyl
+//     fluid-batch --restart runs/cyl/checkpoint.bin --steps 5000 --viscosity 0.01 --out runs/nu01
+
+use fluid::checkpoint::{load_checkpoint, save_checkpoint};
+use fluid::csv::{write_fields, ForceWriter};
+use fluid::lattice_state::{viscosity_from_tau, DEFAULT_TAU};
+use fluid::npy::{write_npy, write_npz};
+use fluid::render::{render_frame, RenderOptions};
+use fluid::scenario::{build_scenario, SCENARIOS};
+use fluid::vtk::{write_vti, write_vtk};
+use fluid::{Kernel, ScenarioFile};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Instant;
+
+// shape, steps and viscosity override the values of a scenario file,
+// on a restart steps counts the additional steps and viscosity replaces the one of every cell
+struct Args {
+    shape: Option<(usize, usize)>,
+    steps: Option<usize>,
+    viscosity: Option<f32>,
+    scenario: String, // a built-in name or a path to a .toml file
+    out: PathBuf,
+    output_every: usize, // 0 writes only the final state
+    formats: Vec<String>, // of the field snapshots
+    frame_every: usize,   // 0 renders no PNG frames
+    render: RenderOptions,
+    checkpoint_every: usize, // 0 saves a checkpoint only at the end
+    restart: Option<PathBuf>,
+    threads: usize, // 0 uses every core
+    kernel: Option<Kernel>, // the one of the checkpoint on a restart, else the reference kernel
+}
+
+fn usage() -> String {
+    format!(
+        "usage: fluid-batch [--scenario NAME|FILE.toml] [--shape NXxNY] [--steps N] \
+         [--viscosity NU] [--out DIR] [--output-every N] [--format LIST] [--checkpoint-every N] \
+         [--restart CHECKPOINT] [--threads N] [--kernel reference|fused|aa] \
+         [--frame-every N] [--frame-scale PIXELS] [--max-density RHO]\n  \
+         scenarios: {}\n  formats, comma separated: {}",
+        SCENARIOS.join(", "),
+        FORMATS.join(", ")
+    )
+}
+
+// npy writes one file per array, fields_00001000_density.npy and so on
+const FORMATS: [&str; 5] = ["csv", "vtk", "vti", "npy", "npz"];
+
+fn parse_formats(value: &str) -> Result<Vec<String>, String> {
+    let formats: Vec<String> = value.split(',').map(|format| format.trim().to_string()).collect();
+    if let Some(format) = formats.iter().find(|format| !FORMATS.contains(&format.as_str())) {
+        return Err(format!("unknown format '{}', expected {}", format, FORMATS.join(", ")));
+    }
+    Ok(formats)
+}
+
+fn parse_shape(value: &str) -> Result<(usize, usize), String> {
+    let (nx, ny) = value
+        .split_once('x')
+        .ok_or_else(|| format!("shape must look like 200x100, got '{}'", value))?;
+    let nx = nx.parse().map_err(|_| format!("bad shape '{}'", value))?;
+    let ny = ny.parse().map_err(|_| format!("bad shape '{}'", value))?;
+    if nx < 2 || ny < 2 {
+        return Err(format!("shape must be at least 2x2, got '{}'", value));
+    }
+    Ok((nx, ny))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        shape: None,
+        steps: None,
+        viscosity: None,
+        scenario: "plate".to_string(),
+        out: PathBuf::from("output"),
+        output_every: 0,
+        formats: vec!["csv".to_string()],
+        frame_every: 0,
+        render: RenderOptions::default(),
+        checkpoint_every: 0,
+        restart: None,
+        threads: 0,
+        kernel: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        if flag == "--help" || flag == "-h" {
+            println!("{}", usage());
+            exit(0);
+        }
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let bad_value = || format!("bad value '{}' for {}", value, flag);
+        match flag.as_str() {
+            "--shape" => args.shape = Some(parse_shape(&value)?),
+            "--steps" => args.steps = Some(value.parse().map_err(|_| bad_value())?),
+            "--viscosity" => args.viscosity = Some(value.parse().map_err(|_| bad_value())?),
+            "--scenario" => args.scenario = value,
+            "--out" => args.out = PathBuf::from(value),
+            "--output-every" => args.output_every = value.parse().map_err(|_| bad_value())?,
+            "--format" => args.formats = parse_formats(&value)?,
+            "--frame-every" => args.frame_every = value.parse().map_err(|_| bad_value())?,
+            "--frame-scale" => args.render.scale = value.parse().map_err(|_| bad_value())?,
+            "--max-density" => args.render.max_density = value.parse().map_err(|_| bad_value())?,
+            "--checkpoint-every" => {
+                args.checkpoint_every = value.parse().map_err(|_| bad_value())?
+            }
+            "--restart" => args.restart = Some(PathBuf::from(value)),
+            "--threads" => args.threads = value.parse().map_err(|_| bad_value())?,
+            "--kernel" => {
+                args.kernel = Some(match value.as_str() {
+                    "reference" => Kernel::Reference,
+                    "fused" => Kernel::FusedPull,
+                    "aa" => Kernel::AaPattern,
+                    _ => return Err(bad_value()),
+                })
+            }
+            _ => return Err(format!("unknown argument {}\n{}", flag, usage())),
+        }
+    }
+    if let Some(viscosity) = args.viscosity.filter(|viscosity| *viscosity <= 0.) {
+        return Err(format!("viscosity must be positive, got {}", viscosity));
+    }
+    if args.render.scale == 0 || args.render.max_density <= 0. {
+        return Err("frame scale and max density must be positive".to_string());
+    }
+    if args.restart.is_some() && args.shape.is_some() {
+        return Err("the shape of a restarted run is the one of the checkpoint".to_string());
+    }
+    Ok(args)
+}
+
+fn run(args: Args) -> Result<(), String> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .map_err(|e| format!("cannot start {} threads: {}", args.threads, e))?;
+    std::fs::create_dir_all(&args.out)
+        .map_err(|e| format!("cannot create {}: {}", args.out.display(), e))?;
+    let (mut fluid_sim, steps, description) = if let Some(path) = &args.restart {
+        let mut fluid_sim = load_checkpoint(path)
+            .map_err(|e| format!("cannot load {}: {}", path.display(), e))?;
+        if let Some(viscosity) = args.viscosity {
+            fluid_sim.set_viscosity(viscosity)?;
+        }
+        let description = format!("{} from step {}", path.display(), fluid_sim.step);
+        (fluid_sim, args.steps.unwrap_or(1000), description)
+    } else if args.scenario.ends_with(".toml") {
+        let mut scenario = ScenarioFile::load(Path::new(&args.scenario))?;
+        scenario.grid.shape = args.shape.unwrap_or(scenario.grid.shape);
+        scenario.viscosity = args.viscosity.unwrap_or(scenario.viscosity);
+        scenario.steps = args.steps.unwrap_or(scenario.steps);
+        let fluid_sim = scenario.build()?;
+        // keep the setup that produced the results next to them, with the overrides applied
+        scenario.save(&args.out.join("scenario.toml"))?;
+        let description = format!("{}, viscosity {}", args.scenario, scenario.viscosity);
+        (fluid_sim, scenario.steps, description)
+    } else {
+        let shape = args.shape.unwrap_or((200, 100));
+        let viscosity = args.viscosity.unwrap_or(viscosity_from_tau(DEFAULT_TAU));
+        let fluid_sim = build_scenario(&args.scenario, shape, viscosity)?;
+        let description = format!("{}, viscosity {}", args.scenario, viscosity);
+        (fluid_sim, args.steps.unwrap_or(1000), description)
+    };
+    if let Some(kernel) = args.kernel {
+        fluid_sim.set_kernel(kernel);
+    }
+    let shape = fluid_sim.shape;
+    let write_snapshot = |fluid_sim: &fluid::LBFluidSim| {
+        let stem = format!("fields_{:08}", fluid_sim.step);
+        for format in &args.formats {
+            let path = args.out.join(format!("{}.{}", stem, format));
+            let result = match format.as_str() {
+                "vtk" => write_vtk(fluid_sim, &path),
+                "vti" => write_vti(fluid_sim, &path),
+                "npy" => write_npy(fluid_sim, &args.out, &stem),
+                "npz" => write_npz(fluid_sim, &path),
+                _ => write_fields(fluid_sim, &path),
+            };
+            result.map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+        }
+        Ok::<_, String>(())
+    };
+    let write_checkpoint = |fluid_sim: &fluid::LBFluidSim| {
+        let path = args.out.join("checkpoint.bin");
+        save_checkpoint(fluid_sim, &path)
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    };
+
+    println!(
+        "{} on {}x{}, {} steps, {} threads -> {}",
+        description,
+        shape.0,
+        shape.1,
+        steps,
+        rayon::current_num_threads(),
+        args.out.display()
+    );
+    let forces_path = args.out.join("forces.csv");
+    let forces_error = |e: std::io::Error| format!("cannot write {}: {}", forces_path.display(), e);
+    // a restart into the directory of the run it comes from continues its forces.csv
+    let forces = match args.restart {
+        Some(_) => ForceWriter::resume(&forces_path, fluid_sim.step),
+        None => ForceWriter::create(&forces_path),
+    };
+    let mut forces = forces.map_err(forces_error)?;
+    let start = Instant::now();
+    for _ in 0..steps {
+        fluid_sim.update();
+        forces.write_forces(fluid_sim.step, &fluid_sim.obstacle_forces).map_err(forces_error)?;
+        if args.output_every > 0 && fluid_sim.step % args.output_every == 0 {
+            write_snapshot(&fluid_sim)?;
+            println!("step {}", fluid_sim.step);
+        }
+        if args.frame_every > 0 && fluid_sim.step % args.frame_every == 0 {
+            let path = args.out.join(format!("frame_{:08}.png", fluid_sim.step));
+            render_frame(&fluid_sim, &args.render)
+                .write_png(&path)
+                .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+        }
+        if args.checkpoint_every > 0 && fluid_sim.step % args.checkpoint_every == 0 {
+            write_checkpoint(&fluid_sim)?;
+        }
+    }
+    let seconds = start.elapsed().as_secs_f64();
+
+    if args.output_every == 0 || fluid_sim.step % args.output_every != 0 {
+        write_snapshot(&fluid_sim)?;
+    }
+    forces.finish().map_err(forces_error)?;
+    write_checkpoint(&fluid_sim)?;
+
+    let cell_updates = (shape.0 * shape.1 * steps) as f64;
+    println!("done in {:.1} s, {:.2} MLUPS", seconds, cell_updates / seconds / 1e6);
+    Ok(())
+}
+
+fn main() {
+    let result = parse_args().and_then(run);
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        exit(1);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::lattice_state::{Cell, FluidState, E_I, OPPOSITE};
+use crate::tup2::Tup2;
+
+// direction index with the x, resp. y, component of E_I flipped
+pub const MIRROR_X: [usize; 9] = [0, 3, 2, 1, 4, 6, 5, 8, 7];
+pub const MIRROR_Y: [usize; 9] = [0, 1, 4, 3, 2, 8, 7, 6, 5];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+impl Edge {
+    pub const ALL: [Edge; 4] = [Edge::Left, Edge::Right, Edge::Bottom, Edge::Top];
+
+    pub fn inward_normal(self) -> Tup2<f32> {
+        match self {
+            Edge::Left => Tup2(1., 0.),
+            Edge::Right => Tup2(-1., 0.),
+            Edge::Bottom => Tup2(0., 1.),
+            Edge::Top => Tup2(0., -1.),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum EdgeBoundary {
+    #[default]
+    Periodic,
+    Wall,                  // no-slip halfway bounce-back
+    MovingWall(Tup2<f32>), // no-slip wall sliding along the edge with the given velocity
+    FreeSlip,              // specular reflection, no friction along the edge
+    Open,                  // zero-gradient outflow
+    Velocity(Tup2<f32>),   // Zou-He prescribed velocity
+    Density(f32),          // Zou-He prescribed density, the velocity is normal to the edge
+}
+
+impl EdgeBoundary {
+    pub fn is_periodic(self) -> bool {
+        matches!(self, EdgeBoundary::Periodic)
+    }
+
+    // velocity of a no-slip wall, none for the other boundaries
+    pub fn wall_velocity(self) -> Option<Tup2<f32>> {
+        match self {
+            EdgeBoundary::Wall => Some(Tup2(0., 0.)),
+            EdgeBoundary::MovingWall(velocity) => Some(velocity),
+            _ => None,
+        }
+    }
+
+    // Open, Velocity and Density fill the populations coming from outside after streaming
+    pub fn rebuilds_incoming(self) -> bool {
+        matches!(self, EdgeBoundary::Open | EdgeBoundary::Velocity(_) | EdgeBoundary::Density(_))
+    }
+
+    // on a fluid cell of the edge, inner is the cell next to it inwards
+    pub fn rebuild_incoming(self, fluid_state: &mut FluidState, inner: &Cell, normal: Tup2<f32>) {
+        match (self, inner) {
+            (EdgeBoundary::Open, Cell::Fluid(inner)) => {
+                Self::apply_zero_gradient(fluid_state, inner, normal)
+            }
+            (EdgeBoundary::Open, Cell::Obstacle) => {}
+            _ => self.apply_zou_he(fluid_state, normal),
+        }
+    }
+
+    // the populations coming from outside the domain are copied from the next cell inwards
+    pub fn apply_zero_gradient(
+        fluid_state: &mut FluidState,
+        inner: &FluidState,
+        normal: Tup2<f32>,
+    ) {
+        for i in 0..9 {
+            if E_I[i].dot(normal) > 0. {
+                fluid_state.d_i[i] = inner.d_i[i];
+            }
+        }
+    }
+
+    // Zou-He on a fluid cell lying on the edge, the populations coming from outside the domain
+    // are rebuilt from the known ones by bouncing back their non-equilibrium part
+    pub fn apply_zou_he(self, fluid_state: &mut FluidState, normal: Tup2<f32>) {
+        let (mut tangential_sum, mut outgoing_sum) = (0., 0.);
+        for i in 0..9 {
+            let e_n = E_I[i].dot(normal);
+            if e_n == 0. {
+                tangential_sum += fluid_state.d_i[i];
+            } else if e_n < 0. {
+                outgoing_sum += fluid_state.d_i[i];
+            }
+        }
+        let known_sum = tangential_sum + 2. * outgoing_sum;
+        let (density, velocity) = match self {
+            EdgeBoundary::Velocity(velocity) => (known_sum / (1. - velocity.dot(normal)), velocity),
+            EdgeBoundary::Density(density) => (density, normal * (1. - known_sum / density)),
+            _ => return,
+        };
+
+        let d_eq = FluidState::get_equilibrium_density(density, velocity);
+        let tangent = Tup2(normal.1, -normal.0);
+        let mut transverse = 0.;
+        for i in 0..9 {
+            if E_I[i].dot(normal) == 0. {
+                transverse += 0.5 * (fluid_state.d_i[i] - d_eq[i]) * E_I[i].dot(tangent);
+            }
+        }
+        for i in 0..9 {
+            if E_I[i].dot(normal) > 0. {
+                let opp = OPPOSITE[i];
+                fluid_state.d_i[i] =
+                    fluid_state.d_i[opp] + d_eq[i] - d_eq[opp] - E_I[i].dot(tangent) * transverse;
+            }
+        }
+        fluid_state.density = density;
+        fluid_state.velocity = velocity;
+    }
+
+    // a corner between two edges that both rebuild their incoming populations misses too many
+    // of them for either, it gets the equilibrium of the velocity and density the edges
+    // prescribe, what neither prescribes taken from inner, the cell diagonally inwards
+    pub fn corner_equilibrium(x_edge: Self, y_edge: Self, inner: &Cell) -> FluidState {
+        let (mut density, mut velocity) = match inner {
+            Cell::Fluid(inner) => {
+                let mut inner = *inner;
+                inner.density = inner.density_sum();
+                (inner.density, inner.velocity_sum(Tup2(0., 0.)))
+            }
+            Cell::Obstacle => (1., Tup2(0., 0.)),
+        };
+        // a density edge lets the fluid through normally only
+        for (edge, tangential) in [(y_edge, &mut velocity.0), (x_edge, &mut velocity.1)] {
+            if let EdgeBoundary::Density(_) = edge {
+                *tangential = 0.;
+            }
+        }
+        for edge in [y_edge, x_edge] {
+            match edge {
+                EdgeBoundary::Velocity(edge_velocity) => velocity = edge_velocity,
+                EdgeBoundary::Density(edge_density) => density = edge_density,
+                _ => {}
+            }
+        }
+        FluidState::new_equilibrium(density, velocity)
+    }
+}
+
+// boundary type of each edge of the domain, periodic edges have to come in opposite pairs
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BoundaryConfig {
+    pub left: EdgeBoundary,
+    pub right: EdgeBoundary,
+    pub bottom: EdgeBoundary,
+    pub top: EdgeBoundary,
+}
+
+impl BoundaryConfig {
+    pub fn new(
+        left: EdgeBoundary,
+        right: EdgeBoundary,
+        bottom: EdgeBoundary,
+        top: EdgeBoundary,
+    ) -> Self {
+        assert_eq!(
+            left.is_periodic(),
+            right.is_periodic(),
+            "left and right edges must both be periodic or both not"
+        );
+        assert_eq!(
+            bottom.is_periodic(),
+            top.is_periodic(),
+            "bottom and top edges must both be periodic or both not"
+        );
+        BoundaryConfig {
+            left,
+            right,
+            bottom,
+            top,
+        }
+    }
+
+    // closed box of no-slip walls
+    pub fn walls() -> Self {
+        Self::new(
+            EdgeBoundary::Wall,
+            EdgeBoundary::Wall,
+            EdgeBoundary::Wall,
+            EdgeBoundary::Wall,
+        )
+    }
+
+    // velocity inlet on the left, density outlet on the right
+    pub fn inlet_outlet(velocity: Tup2<f32>, density: f32, top_bottom: EdgeBoundary) -> Self {
+        Self::new(
+            EdgeBoundary::Velocity(velocity),
+            EdgeBoundary::Density(density),
+            top_bottom,
+            top_bottom,
+        )
+    }
+
+    pub fn get(&self, edge: Edge) -> EdgeBoundary {
+        match edge {
+            Edge::Left => self.left,
+            Edge::Right => self.right,
+            Edge::Bottom => self.bottom,
+            Edge::Top => self.top,
+        }
+    }
+}
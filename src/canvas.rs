@@ -1,20 +1,17 @@
 use crate::scene2d::Scene2D;
 use crate::vec2::Vec2;
-use ndarray::{Array1, Array2};
-use palette::{FromColor, Hsl, Srgb};
+use ndarray::Array1;
 use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
 use sdl2::render::WindowCanvas;
 use crate::fluid_struct::LBFluidSim;
-use crate::lattice_state::{Cell, FluidState};
+use crate::color::{
+    density_color, normalize, ColorRange, Colormap, ARROW_COLOR, OBSTACLE_COLOR,
+};
+use crate::fields::ScalarField;
+use crate::lattice_state::Cell;
 use crate::obstacle_board::GridStats;
 
-fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
-    let hsl = Hsl::new(h, s, l);
-    let rgb: Srgb = Srgb::from_color(hsl);
-    let (r, g, b) = rgb.into_components();
-    ((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8)
-}
 pub struct Canvas2D {
     pub canvas: WindowCanvas,
     pub size: Vec2<f32>,
@@ -22,6 +19,9 @@ pub struct Canvas2D {
     pub canvas_center: Vec2<f32>,
     pub bg_color: (u8, u8, u8),
     pub scene2d_list: Vec<Scene2D>,
+    pub field: ScalarField, // drawn by draw_fluid_field
+    pub colormap: Colormap,
+    pub color_range: ColorRange,
 }
 
 impl Canvas2D{
@@ -40,9 +40,19 @@ impl Canvas2D{
             canvas_center,
             bg_color,
             scene2d_list,
+            field: ScalarField::Density,
+            colormap: Colormap::Viridis,
+            color_range: ColorRange::Auto,
         }
     }
 
+    // with the colormap suiting its sign and an automatic range
+    pub fn select_field(&mut self, field: ScalarField) {
+        self.field = field;
+        self.colormap = if field.is_signed() { Colormap::Diverging } else { Colormap::Viridis };
+        self.color_range = ColorRange::Auto;
+    }
+
     pub fn correct_coord_to_point(&self, point: Vec2<f32>) -> Point {
         let point = (point.negate_y() * self.canvas_zoom + self.size / 2.).to_i32();
         Point::new(point.x, point.y)
@@ -197,15 +207,9 @@ impl Canvas2D{
         let grid_pos = &grid_stats.grid_pos;
         for i in 0..fluid.shape.0 {
             for j in 0..fluid.shape.1 {
-                let density = match fluid.states_curr[[i, j]] {
-                    Cell::Fluid(fluid_state) => fluid_state.density,
-                    Cell::Obstacle => -1.,
-                };
-                let color = if density < 0. {
-                    (255, 0, 0)
-                } else {
-                    let density = (density / max_density).clamp(0., 1.);
-                    hsl_to_rgb(240., 1., 0.5 * density)
+                let color = match fluid.lattice.get(i, j) {
+                    Cell::Fluid(fluid_state) => density_color(fluid_state.density, max_density),
+                    Cell::Obstacle => OBSTACLE_COLOR,
                 };
 
                 self.draw_square_s2(
@@ -217,17 +221,47 @@ impl Canvas2D{
             }
         }
     }
+    // colors the selected field and returns the range mapped to the colormap
+    pub fn draw_fluid_field(
+        &mut self,
+        grid_stats: &GridStats,
+        fluid: &LBFluidSim,
+        scene_num: usize,
+    ) -> (f32, f32) {
+        let values = self.field.compute(fluid);
+        let fluid_values = values
+            .iter()
+            .zip(&fluid.lattice.obstacle)
+            .filter(|(_, obstacle)| !**obstacle)
+            .map(|(value, _)| *value);
+        let range = self.color_range.resolve(fluid_values, self.field.is_signed());
+        let grid_pos = &grid_stats.grid_pos;
+        for i in 0..fluid.shape.0 {
+            for j in 0..fluid.shape.1 {
+                let color = match fluid.lattice.get(i, j) {
+                    Cell::Fluid(_) => self.colormap.color(normalize(values[[i, j]], range)),
+                    Cell::Obstacle => OBSTACLE_COLOR,
+                };
+                self.draw_square_s2(
+                    grid_pos[[i, j]].to_vec2(),
+                    grid_stats.spacing,
+                    color,
+                    scene_num,
+                )
+            }
+        }
+        range
+    }
     pub fn draw_fluid_velocity(&mut self, grid_stats: &GridStats, fluid: &LBFluidSim, max_len: f32, jump_index: usize, scene_num: usize){
         self.canvas
-            .set_draw_color(Color::RGB(255, 255, 255));
+            .set_draw_color(Color::RGB(ARROW_COLOR.0, ARROW_COLOR.1, ARROW_COLOR.2));
         for i in (0..grid_stats.shape.0).step_by(jump_index){
             for j in (0..grid_stats.shape.1).step_by(jump_index){
                 let pos = grid_stats.grid_pos[[i, j]];
-                let state = fluid.states_curr[[i, j]];
+                let state = fluid.lattice.get(i, j);
                 match state {
                     Cell::Obstacle => {},
                     Cell::Fluid(fluid_state) => {
-                        let vel = fluid_state.velocity;
                         let normed_vel = fluid_state.velocity.normalize() * max_len;
                         
                         self.draw_line_s2(
@@ -0,0 +1,472 @@
+use crate::boundary::{BoundaryConfig, EdgeBoundary};
+use crate::collision::{CollisionModel, CollisionOperator, Smagorinsky};
+use crate::fluid_struct::{Kernel, LBFluidSim, Phase};
+use crate::lattice_state::{Cell, FluidState, MrtRates};
+use crate::obstacle_board::{GridStats, ObstacleBoard};
+use crate::tup2::Tup2;
+use bit_vec::BitVec;
+use ndarray::Array2;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+// binary snapshot of a whole simulation, little-endian, in this order:
+//   magic, version, grid shape and length, step, boundaries, collision model, Smagorinsky constant,
+//   interpolated bounce-back flag, phase of the distributions, kernel, omega and force of every
+//   cell, obstacle bits, wall velocities, link fractions, the distributions, density and velocity
+//   of every fluid cell, the distributions of every obstacle cell in the pushed phase only, steps
+//   the force history keeps, the obstacle forces of the last step and the force history
+// cells are written column by column in the order of the ndarray arrays
+const MAGIC: &[u8; 8] = b"LBMCKPT\0";
+const VERSION: u32 = 1;
+
+// a custom collision model is written as this tag, only the *_with functions restore it
+const CUSTOM_COLLISION: u8 = 255;
+
+pub fn save_checkpoint(fluid_sim: &LBFluidSim, path: &Path) -> Result<()> {
+    // written next to the target first, so a run killed while saving keeps the previous checkpoint
+    let partial = path.with_extension("partial");
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    write_checkpoint(fluid_sim, &mut writer)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&partial, path)
+}
+
+pub fn load_checkpoint(path: &Path) -> Result<LBFluidSim> {
+    load(path, None)
+}
+
+// for a checkpoint of a run with a custom collision operator, which the file cannot hold
+pub fn load_checkpoint_with<C: CollisionOperator + 'static>(
+    path: &Path,
+    collision: C,
+) -> Result<LBFluidSim> {
+    load(path, Some(Box::new(collision)))
+}
+
+fn load(path: &Path, custom: Option<Box<dyn CollisionOperator>>) -> Result<LBFluidSim> {
+    read(&mut BufReader::new(File::open(path)?), custom).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => invalid("checkpoint file is truncated".to_string()),
+        _ => e,
+    })
+}
+
+pub fn write_checkpoint(fluid_sim: &LBFluidSim, writer: &mut impl Write) -> Result<()> {
+    let board = &fluid_sim.obstacle_board;
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    write_u64(writer, fluid_sim.shape.0)?;
+    write_u64(writer, fluid_sim.shape.1)?;
+    write_f32(writer, board.grid_stats.length)?;
+    write_u64(writer, fluid_sim.step)?;
+
+    let b = &fluid_sim.boundaries;
+    for edge in [b.left, b.right, b.bottom, b.top] {
+        let (tag, p0, p1) = match edge {
+            EdgeBoundary::Periodic => (0, 0., 0.),
+            EdgeBoundary::Wall => (1, 0., 0.),
+            EdgeBoundary::MovingWall(velocity) => (2, velocity.0, velocity.1),
+            EdgeBoundary::FreeSlip => (3, 0., 0.),
+            EdgeBoundary::Open => (4, 0., 0.),
+            EdgeBoundary::Velocity(velocity) => (5, velocity.0, velocity.1),
+            EdgeBoundary::Density(density) => (6, density, 0.),
+        };
+        writer.write_all(&[tag])?;
+        write_f32(writer, p0)?;
+        write_f32(writer, p1)?;
+    }
+
+    let (tag, p0, p1) = match fluid_sim.collision.model() {
+        Some(CollisionModel::Bgk) => (0, 0., 0.),
+        Some(CollisionModel::Mrt(rates)) => (1, rates.bulk, rates.ghost),
+        Some(CollisionModel::Trt(magic)) => (2, magic, 0.),
+        None => (CUSTOM_COLLISION, 0., 0.),
+    };
+    writer.write_all(&[tag])?;
+    write_f32(writer, p0)?;
+    write_f32(writer, p1)?;
+    // a negative constant means no LES model
+    write_f32(writer, fluid_sim.les.map_or(-1., |les| les.constant))?;
+    writer.write_all(&[fluid_sim.interpolated_bounce_back as u8])?;
+    let phase = match fluid_sim.phase {
+        Phase::Streamed => 0,
+        Phase::Collided => 1,
+        Phase::Swapped => 2,
+        Phase::Pushed => 3,
+    };
+    writer.write_all(&[phase])?;
+    let kernel = match fluid_sim.kernel {
+        Kernel::Reference => 0,
+        Kernel::FusedPull => 1,
+        Kernel::AaPattern => 2,
+    };
+    writer.write_all(&[kernel])?;
+
+    for omega in fluid_sim.omega.iter() {
+        write_f32(writer, *omega)?;
+    }
+    for force in fluid_sim.force.iter() {
+        write_tup2(writer, *force)?;
+    }
+
+    writer.write_all(&board.bit_vec.to_bytes())?;
+    // sorted, so the same state always gives the same file
+    let mut wall_velocity: Vec<_> = board.wall_velocity.iter().collect();
+    wall_velocity.sort_by_key(|(index, _)| **index);
+    write_u64(writer, wall_velocity.len())?;
+    for (index, velocity) in wall_velocity {
+        write_u64(writer, *index)?;
+        write_tup2(writer, *velocity)?;
+    }
+    let mut link_fraction: Vec<_> = board.link_fraction.iter().collect();
+    link_fraction.sort_by_key(|(key, _)| **key);
+    write_u64(writer, link_fraction.len())?;
+    for ((index, dir), fraction) in link_fraction {
+        write_u64(writer, *index)?;
+        writer.write_all(&[*dir as u8])?;
+        write_f32(writer, *fraction)?;
+    }
+
+    for cell in fluid_sim.lattice.cells() {
+        if let Cell::Fluid(fs) = cell {
+            for d in fs.d_i {
+                write_f32(writer, d)?;
+            }
+            write_f32(writer, fs.density)?;
+            write_tup2(writer, fs.velocity)?;
+        }
+    }
+    // the AA kernel keeps populations streaming into an obstacle in its slots until they bounce
+    if fluid_sim.phase == Phase::Pushed {
+        let lattice = &fluid_sim.lattice;
+        for ((i, j), _) in lattice.obstacle.indexed_iter().filter(|(_, obstacle)| **obstacle) {
+            for q in 0..9 {
+                write_f32(writer, lattice.d_i[[q, i, j]])?;
+            }
+        }
+    }
+
+    write_u64(writer, fluid_sim.force_history_limit)?;
+    write_u64(writer, fluid_sim.force_history.len())?;
+    write_u64(writer, fluid_sim.obstacle_forces.len())?;
+    for force in &fluid_sim.obstacle_forces {
+        write_tup2(writer, *force)?;
+    }
+    for forces in &fluid_sim.force_history {
+        for force in forces {
+            write_tup2(writer, *force)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_checkpoint(reader: &mut impl Read) -> Result<LBFluidSim> {
+    read(reader, None)
+}
+
+pub fn read_checkpoint_with<C: CollisionOperator + 'static>(
+    reader: &mut impl Read,
+    collision: C,
+) -> Result<LBFluidSim> {
+    read(reader, Some(Box::new(collision)))
+}
+
+fn read(
+    reader: &mut impl Read,
+    custom: Option<Box<dyn CollisionOperator>>,
+) -> Result<LBFluidSim> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a checkpoint file".to_string()));
+    }
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != VERSION {
+        return Err(invalid(format!(
+            "checkpoint version {} is not supported, expected {}",
+            version, VERSION
+        )));
+    }
+    let shape = (read_u64(reader)?, read_u64(reader)?);
+    let length = read_f32(reader)?;
+    if shape.0 < 2 || shape.1 < 2 || shape.0.saturating_mul(shape.1) > 1 << 32 {
+        return Err(invalid(format!("bad grid shape {}x{}", shape.0, shape.1)));
+    }
+    let cell_count = shape.0 * shape.1;
+    let step = read_u64(reader)?;
+
+    let mut edges = [EdgeBoundary::Periodic; 4];
+    for edge in edges.iter_mut() {
+        let tag = read_u8(reader)?;
+        let (p0, p1) = (read_f32(reader)?, read_f32(reader)?);
+        *edge = match tag {
+            0 => EdgeBoundary::Periodic,
+            1 => EdgeBoundary::Wall,
+            2 => EdgeBoundary::MovingWall(Tup2(p0, p1)),
+            3 => EdgeBoundary::FreeSlip,
+            4 => EdgeBoundary::Open,
+            5 => EdgeBoundary::Velocity(Tup2(p0, p1)),
+            6 => EdgeBoundary::Density(p0),
+            _ => return Err(invalid(format!("unknown boundary tag {}", tag))),
+        };
+    }
+    if edges[0].is_periodic() != edges[1].is_periodic()
+        || edges[2].is_periodic() != edges[3].is_periodic()
+    {
+        return Err(invalid("opposite edges must both be periodic or both not".to_string()));
+    }
+    let boundaries = BoundaryConfig::new(edges[0], edges[1], edges[2], edges[3]);
+
+    let tag = read_u8(reader)?;
+    let (p0, p1) = (read_f32(reader)?, read_f32(reader)?);
+    let model = match tag {
+        0 => Some(CollisionModel::Bgk),
+        1 => Some(CollisionModel::Mrt(MrtRates { bulk: p0, ghost: p1 })),
+        2 => Some(CollisionModel::Trt(p0)),
+        CUSTOM_COLLISION => None,
+        _ => return Err(invalid(format!("unknown collision tag {}", tag))),
+    };
+    let collision = match (model, custom) {
+        (Some(model), None) => model.to_operator(),
+        (None, Some(custom)) => custom,
+        (None, None) => {
+            return Err(invalid(
+                "checkpoint has a custom collision operator, load it with load_checkpoint_with"
+                    .to_string(),
+            ))
+        }
+        (Some(model), Some(_)) => {
+            return Err(invalid(format!(
+                "checkpoint has the built-in collision model {:?}, not a custom one",
+                model
+            )))
+        }
+    };
+    let les_constant = read_f32(reader)?;
+    let interpolated_bounce_back = read_u8(reader)? != 0;
+    let phase = read_u8(reader)?;
+    let phase = match phase {
+        0 => Phase::Streamed,
+        1 => Phase::Collided,
+        2 => Phase::Swapped,
+        3 => Phase::Pushed,
+        _ => return Err(invalid(format!("unknown phase tag {}", phase))),
+    };
+    let kernel = read_u8(reader)?;
+    let kernel = match kernel {
+        0 => Kernel::Reference,
+        1 => Kernel::FusedPull,
+        2 => Kernel::AaPattern,
+        _ => return Err(invalid(format!("unknown kernel tag {}", kernel))),
+    };
+
+    let omega = (0..cell_count).map(|_| read_f32(reader)).collect::<Result<Vec<_>>>()?;
+    let force = (0..cell_count).map(|_| read_tup2(reader)).collect::<Result<Vec<_>>>()?;
+
+    let mut bits = vec![0; cell_count.div_ceil(8)];
+    reader.read_exact(&mut bits)?;
+    let mut bit_vec = BitVec::from_bytes(&bits);
+    bit_vec.truncate(cell_count);
+    let mut obstacle_board = ObstacleBoard::new_empty(GridStats::new(shape, length));
+    obstacle_board.bit_vec = bit_vec;
+    for _ in 0..read_u64(reader)? {
+        let index = read_index(reader, cell_count)?;
+        obstacle_board.wall_velocity.insert(index, read_tup2(reader)?);
+    }
+    for _ in 0..read_u64(reader)? {
+        let index = read_index(reader, cell_count)?;
+        let dir = read_u8(reader)? as usize;
+        if dir >= 9 {
+            return Err(invalid(format!("bad link direction {}", dir)));
+        }
+        obstacle_board.link_fraction.insert((index, dir), read_f32(reader)?);
+    }
+
+    // the initial fields are overwritten below, only the cell types are kept
+    let mut fluid_sim =
+        LBFluidSim::new(obstacle_board, |_| 1., |_| Tup2(0., 0.), 1., boundaries);
+    for i in 0..shape.0 {
+        for j in 0..shape.1 {
+            if fluid_sim.lattice.is_obstacle(i, j) {
+                continue;
+            }
+            let mut d_i = [0.; 9];
+            for d in d_i.iter_mut() {
+                *d = read_f32(reader)?;
+            }
+            let density = read_f32(reader)?;
+            let fs = FluidState::new(d_i, read_tup2(reader)?, density);
+            fluid_sim.lattice.set(i, j, &fs);
+        }
+    }
+    if phase == Phase::Pushed {
+        for i in 0..shape.0 {
+            for j in 0..shape.1 {
+                if !fluid_sim.lattice.is_obstacle(i, j) {
+                    continue;
+                }
+                for q in 0..9 {
+                    fluid_sim.lattice.d_i[[q, i, j]] = read_f32(reader)?;
+                }
+            }
+        }
+    }
+    fluid_sim.omega = Array2::from_shape_vec(shape, omega).map_err(|e| invalid(e.to_string()))?;
+    fluid_sim.force = Array2::from_shape_vec(shape, force).map_err(|e| invalid(e.to_string()))?;
+    fluid_sim.collision = collision;
+    fluid_sim.les = (les_constant >= 0.).then(|| Smagorinsky::new(les_constant));
+    fluid_sim.interpolated_bounce_back = interpolated_bounce_back;
+    fluid_sim.phase = phase;
+    fluid_sim.set_kernel(kernel);
+    fluid_sim.step = step;
+
+    let history_limit = read_u64(reader)?;
+    let history_len = read_u64(reader)?;
+    let obstacle_count = read_u64(reader)?;
+    if obstacle_count != fluid_sim.obstacle_forces.len() {
+        return Err(invalid(format!(
+            "checkpoint has forces on {} obstacles, the obstacle bits give {}",
+            obstacle_count,
+            fluid_sim.obstacle_forces.len()
+        )));
+    }
+    fluid_sim.obstacle_forces = read_forces(reader, obstacle_count)?;
+    for _ in 0..history_len {
+        fluid_sim.force_history.push_back(read_forces(reader, obstacle_count)?);
+    }
+    fluid_sim.record_force_history(history_limit);
+    Ok(fluid_sim)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn write_u64(writer: &mut impl Write, value: usize) -> Result<()> {
+    writer.write_all(&(value as u64).to_le_bytes())
+}
+
+fn write_f32(writer: &mut impl Write, value: f32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_tup2(writer: &mut impl Write, value: Tup2<f32>) -> Result<()> {
+    write_f32(writer, value.0)?;
+    write_f32(writer, value.1)
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<usize> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    usize::try_from(u64::from_le_bytes(bytes)).map_err(|e| invalid(e.to_string()))
+}
+
+fn read_index(reader: &mut impl Read, cell_count: usize) -> Result<usize> {
+    let index = read_u64(reader)?;
+    if index >= cell_count {
+        return Err(invalid(format!("cell index {} outside of the grid", index)));
+    }
+    Ok(index)
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_tup2(reader: &mut impl Read) -> Result<Tup2<f32>> {
+    Ok(Tup2(read_f32(reader)?, read_f32(reader)?))
+}
+
+fn read_forces(reader: &mut impl Read, obstacle_count: usize) -> Result<Vec<Tup2<f32>>> {
+    (0..obstacle_count).map(|_| read_tup2(reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::{Bgk, Trt};
+    use crate::scenario::build_scenario;
+
+    // BGK under another name, so it reports no built-in model
+    struct Custom;
+
+    impl CollisionOperator for Custom {
+        fn collide(&self, fluid_state: &mut FluidState, omega: f32, force: Tup2<f32>) {
+            Bgk.collide(fluid_state, omega, force)
+        }
+    }
+
+    fn saved(fluid_sim: &LBFluidSim) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_checkpoint(fluid_sim, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn custom_collision_needs_the_operator() {
+        let mut fluid_sim = build_scenario("cylinder", (40, 20), 0.05).unwrap();
+        fluid_sim.set_collision(Custom);
+        for _ in 0..5 {
+            fluid_sim.update();
+        }
+        let bytes = saved(&fluid_sim);
+        let error = read_checkpoint(&mut bytes.as_slice()).err().unwrap();
+        assert!(error.to_string().contains("load_checkpoint_with"), "{}", error);
+
+        let mut restored = read_checkpoint_with(&mut bytes.as_slice(), Custom).unwrap();
+        assert!(restored.collision.model().is_none());
+        for _ in 0..5 {
+            fluid_sim.update();
+            restored.update();
+        }
+        assert_eq!(restored.lattice.d_i, fluid_sim.lattice.d_i);
+    }
+
+    #[test]
+    fn built_in_collision_refuses_an_operator() {
+        let mut fluid_sim = build_scenario("cylinder", (40, 20), 0.05).unwrap();
+        fluid_sim.set_collision(Trt::default());
+        let bytes = saved(&fluid_sim);
+        assert!(read_checkpoint_with(&mut bytes.as_slice(), Custom).is_err());
+        let restored = read_checkpoint(&mut bytes.as_slice()).unwrap();
+        assert!(matches!(restored.collision.model(), Some(CollisionModel::Trt(_))));
+    }
+
+    #[test]
+    fn restart_keeps_the_kernel() {
+        for kernel in Kernel::ALL {
+            let mut fluid_sim = build_scenario("cylinder", (40, 20), 0.05).unwrap();
+            fluid_sim.set_kernel(kernel);
+            // an odd number of steps leaves the AA kernel in the pushed phase
+            for _ in 0..3 {
+                fluid_sim.update();
+            }
+            let mut restored = read_checkpoint(&mut saved(&fluid_sim).as_slice()).unwrap();
+            assert_eq!(restored.kernel, kernel);
+            for _ in 0..3 {
+                fluid_sim.update();
+                restored.update();
+            }
+            assert_eq!(restored.lattice.d_i, fluid_sim.lattice.d_i, "{:?}", kernel);
+        }
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let fluid_sim = build_scenario("cylinder", (40, 20), 0.05).unwrap();
+        let mut bytes = saved(&fluid_sim);
+        bytes[8..12].copy_from_slice(&2u32.to_le_bytes());
+        let error = read_checkpoint(&mut bytes.as_slice()).err().unwrap();
+        assert!(error.to_string().contains("version 2"), "{}", error);
+    }
+}
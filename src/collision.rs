@@ -0,0 +1,210 @@
+use crate::lattice_state::{FluidState, MrtRates, C2, E_I, TRT_MAGIC_WALL};
+use crate::tup2::Tup2;
+
+// a collision model relaxes the distributions of one fluid cell, the density and velocity of
+// the cell are already up to date when it is called
+// shared by the threads that collide the columns of the lattice
+pub trait CollisionOperator: Send + Sync {
+    // omega = 1 / tau is the relaxation rate set by the viscosity of the cell,
+    // force is the body force on the cell and has to be added to the distributions here
+    fn collide(&self, fluid_state: &mut FluidState, omega: f32, force: Tup2<f32>);
+
+    // which built-in model this is, for checkpoints, other models have to be passed to
+    // load_checkpoint_with
+    fn model(&self) -> Option<CollisionModel> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CollisionModel {
+    Bgk,
+    Mrt(MrtRates),
+    Trt(f32), // magic parameter
+}
+
+impl CollisionModel {
+    pub fn to_operator(self) -> Box<dyn CollisionOperator> {
+        match self {
+            CollisionModel::Bgk => Box::new(Bgk),
+            CollisionModel::Mrt(rates) => Box::new(Mrt::new(rates)),
+            CollisionModel::Trt(magic) => Box::new(Trt::new(magic)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bgk;
+
+impl CollisionOperator for Bgk {
+    fn collide(&self, fluid_state: &mut FluidState, omega: f32, force: Tup2<f32>) {
+        fluid_state.collide(omega, force)
+    }
+
+    fn model(&self) -> Option<CollisionModel> {
+        Some(CollisionModel::Bgk)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Mrt {
+    pub rates: MrtRates, // the shear rate is the omega of the cell
+}
+
+impl Mrt {
+    pub fn new(rates: MrtRates) -> Self {
+        Mrt { rates }
+    }
+}
+
+impl CollisionOperator for Mrt {
+    fn collide(&self, fluid_state: &mut FluidState, omega: f32, force: Tup2<f32>) {
+        fluid_state.collide_mrt(omega, self.rates, force)
+    }
+
+    fn model(&self) -> Option<CollisionModel> {
+        Some(CollisionModel::Mrt(self.rates))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Trt {
+    pub magic: f32, // the symmetric rate is the omega of the cell
+}
+
+impl Trt {
+    pub fn new(magic: f32) -> Self {
+        Trt { magic }
+    }
+}
+
+impl Default for Trt {
+    fn default() -> Self {
+        Trt {
+            magic: TRT_MAGIC_WALL,
+        }
+    }
+}
+
+impl CollisionOperator for Trt {
+    fn collide(&self, fluid_state: &mut FluidState, omega: f32, force: Tup2<f32>) {
+        fluid_state.collide_trt(omega, self.magic, force)
+    }
+
+    fn model(&self) -> Option<CollisionModel> {
+        Some(CollisionModel::Trt(self.magic))
+    }
+}
+
+// Smagorinsky large eddy model, the sub-grid eddy viscosity is added to the viscosity of the
+// cell through an effective relaxation time computed from the non-equilibrium stress
+#[derive(Clone, Copy, Debug)]
+pub struct Smagorinsky {
+    pub constant: f32,
+}
+
+impl Smagorinsky {
+    pub fn new(constant: f32) -> Self {
+        Smagorinsky { constant }
+    }
+
+    pub fn effective_omega(&self, fluid_state: &FluidState, omega: f32) -> f32 {
+        let d_eq = FluidState::get_equilibrium_density(fluid_state.density, fluid_state.velocity);
+        let (mut p_xx, mut p_xy, mut p_yy) = (0., 0., 0.);
+        for i in 0..9 {
+            let non_eq = fluid_state.d_i[i] - d_eq[i];
+            p_xx += E_I[i].0 * E_I[i].0 * non_eq;
+            p_xy += E_I[i].0 * E_I[i].1 * non_eq;
+            p_yy += E_I[i].1 * E_I[i].1 * non_eq;
+        }
+        let stress = (p_xx * p_xx + 2. * p_xy * p_xy + p_yy * p_yy).sqrt();
+
+        // positive root of tau^2 - tau_0 tau - (C / c_s^2)^2 |stress| / (sqrt(2) rho) = 0,
+        // written for omega so that it comes back unchanged without stress
+        let eddy_term = 2. * std::f32::consts::SQRT_2 * (self.constant / C2).powi(2) * stress
+            / fluid_state.density;
+        2. * omega / (1. + (1. + eddy_term * omega * omega).sqrt())
+    }
+}
+
+impl Default for Smagorinsky {
+    fn default() -> Self {
+        Smagorinsky { constant: 0.1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lattice_state::{MrtRates, TRT_MAGIC_WALL};
+
+    // an equilibrium with some non-equilibrium part added
+    fn off_equilibrium(force: Tup2<f32>) -> FluidState {
+        let mut fluid_state = FluidState::new_equilibrium(1.02, Tup2(0.05, -0.02));
+        let non_eq = [0.004, -0.002, 0.003, 0.001, -0.003, 0.0005, -0.001, 0.002, -0.0015];
+        for i in 0..9 {
+            fluid_state.d_i[i] += non_eq[i];
+        }
+        fluid_state.density = fluid_state.density_sum();
+        fluid_state.velocity = fluid_state.velocity_sum(force);
+        fluid_state
+    }
+
+    fn momentum(fluid_state: &FluidState) -> Tup2<f32> {
+        (0..9).fold(Tup2(0., 0.), |momentum, i| momentum + E_I[i] * fluid_state.d_i[i])
+    }
+
+    #[test]
+    fn collisions_conserve_mass_and_momentum() {
+        let models = [
+            CollisionModel::Bgk,
+            CollisionModel::Mrt(MrtRates::default()),
+            CollisionModel::Trt(TRT_MAGIC_WALL),
+        ];
+        for model in models {
+            // the force adds its momentum
+            for force in [Tup2(0., 0.), Tup2(1e-3, -2e-3)] {
+                let before = off_equilibrium(force);
+                let mut after = before;
+                model.to_operator().collide(&mut after, 1.3, force);
+                let mass = after.density_sum() - before.density_sum();
+                let momentum = momentum(&after) - momentum(&before) - force;
+                assert!(mass.abs() < 1e-6, "{:?}, force {:?}: mass {}", model, force, mass);
+                assert!(
+                    momentum.mag() < 1e-6,
+                    "{:?}, force {:?}: momentum off by {:?}",
+                    model,
+                    force,
+                    momentum
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn smagorinsky_keeps_omega_at_equilibrium() {
+        let les = Smagorinsky::default();
+        let fluid_state = FluidState::new_equilibrium(1.02, Tup2(0.05, -0.02));
+        for k in 1..=190 {
+            let omega = 0.01 * k as f32;
+            assert_eq!(les.effective_omega(&fluid_state, omega), omega);
+        }
+    }
+
+    // a shear stress adds eddy viscosity, the more the larger the constant
+    #[test]
+    fn smagorinsky_lowers_omega_under_shear() {
+        let mut fluid_state = FluidState::new_equilibrium(1.02, Tup2(0.05, -0.02));
+        for (i, sign) in [(5, 1.), (6, -1.), (7, 1.), (8, -1.)] {
+            fluid_state.d_i[i] += sign * 1e-3;
+        }
+        for omega in [0.5, 1., 1.9] {
+            let mut last = omega;
+            for constant in [0.05, 0.1, 0.2] {
+                let effective = Smagorinsky::new(constant).effective_omega(&fluid_state, omega);
+                assert!(effective > 0. && effective < last, "omega {}, C {}", omega, constant);
+                last = effective;
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// colors shared by the SDL viewer and the headless renderer
+
+pub const OBSTACLE_COLOR: (u8, u8, u8) = (255, 0, 0);
+pub const ARROW_COLOR: (u8, u8, u8) = (255, 255, 255);
+
+// hue in degrees, saturation and lightness in 0..1
+pub fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+    let chroma = (1. - (2. * l - 1.).abs()) * s;
+    let sector = h.rem_euclid(360.) / 60.;
+    let x = chroma * (1. - (sector % 2. - 1.).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = l - chroma / 2.;
+    (((r + m) * 255.) as u8, ((g + m) * 255.) as u8, ((b + m) * 255.) as u8)
+}
+
+// from black at zero density to full blue at max_density
+pub fn density_color(density: f32, max_density: f32) -> (u8, u8, u8) {
+    let density = (density / max_density).clamp(0., 1.);
+    hsl_to_rgb(240., 1., 0.5 * density)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Colormap {
+    Blue, // the lightness ramp of density_color
+    Viridis,
+    Magma,
+    Diverging, // blue through white to red, for signed fields centered on zero
+}
+
+// evenly spaced samples of matplotlib's viridis and magma and of ColorBrewer's RdBu
+const VIRIDIS: [u32; 11] = [
+    0x440154, 0x482475, 0x414487, 0x355f8d, 0x2a788e, 0x21918c, 0x22a884, 0x44bf70, 0x7ad151,
+    0xbddf26, 0xfde725,
+];
+const MAGMA: [u32; 11] = [
+    0x000004, 0x140e36, 0x3b0f70, 0x641a80, 0x8c2981, 0xb73779, 0xde4968, 0xf7705c, 0xfe9f6d,
+    0xfecf92, 0xfcfdbf,
+];
+const DIVERGING: [u32; 9] = [
+    0x2166ac, 0x4393c3, 0x92c5de, 0xd1e5f0, 0xf7f7f7, 0xfddbc7, 0xf4a582, 0xd6604d, 0xb2182b,
+];
+
+impl Colormap {
+    pub const ALL: [Colormap; 4] = [
+        Colormap::Blue,
+        Colormap::Viridis,
+        Colormap::Magma,
+        Colormap::Diverging,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Blue => "blue",
+            Colormap::Viridis => "viridis",
+            Colormap::Magma => "magma",
+            Colormap::Diverging => "diverging",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let index = Colormap::ALL.iter().position(|colormap| *colormap == self).unwrap();
+        Colormap::ALL[(index + 1) % Colormap::ALL.len()]
+    }
+
+    // t from 0 to 1, clamped
+    pub fn color(self, t: f32) -> (u8, u8, u8) {
+        let t = if t.is_nan() { 0. } else { t.clamp(0., 1.) };
+        let samples: &[u32] = match self {
+            Colormap::Blue => return hsl_to_rgb(240., 1., 0.5 * t),
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Diverging => &DIVERGING,
+        };
+        // linear between the two nearest samples
+        let position = t * (samples.len() - 1) as f32;
+        let index = (position as usize).min(samples.len() - 2);
+        let fraction = position - index as f32;
+        let channel = |sample: u32, shift: u32| ((sample >> shift) & 0xff) as f32;
+        let mix = |shift| {
+            let (low, high) = (channel(samples[index], shift), channel(samples[index + 1], shift));
+            (low + (high - low) * fraction).round() as u8
+        };
+        (mix(16), mix(8), mix(0))
+    }
+}
+
+// values mapped to the ends of a colormap
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorRange {
+    Auto, // smallest to largest value, or symmetric around zero for a signed field
+    Fixed(f32, f32),
+}
+
+impl ColorRange {
+    // the fixed range, or the one fitting the values
+    pub fn resolve(self, values: impl Iterator<Item = f32>, signed: bool) -> (f32, f32) {
+        match self {
+            ColorRange::Fixed(low, high) => (low, high),
+            ColorRange::Auto => {
+                let (low, high) = values
+                    .filter(|value| value.is_finite())
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), value| {
+                        (low.min(value), high.max(value))
+                    });
+                if low > high {
+                    (0., 1.)
+                } else if signed {
+                    let bound = low.abs().max(high.abs());
+                    (-bound, bound)
+                } else {
+                    (low, high)
+                }
+            }
+        }
+    }
+}
+
+// position of value in low..high, the middle when the range is empty
+pub fn normalize(value: f32, (low, high): (f32, f32)) -> f32 {
+    if high > low {
+        (value - low) / (high - low)
+    } else {
+        0.5
+    }
+}
@@ -0,0 +1,123 @@
+use crate::fluid_struct::LBFluidSim;
+use crate::lattice_state::Cell;
+use crate::tup2::Tup2;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+// one row per cell, obstacle cells have zero density and velocity
+pub fn write_fields(fluid_sim: &LBFluidSim, path: &Path) -> std::io::Result<()> {
+    let grid_pos = &fluid_sim.obstacle_board.grid_stats.grid_pos;
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "i,j,x,y,obstacle,density,ux,uy")?;
+    for i in 0..fluid_sim.shape.0 {
+        for j in 0..fluid_sim.shape.1 {
+            let pos = grid_pos[[i, j]];
+            let (obstacle, density, velocity) = match fluid_sim.lattice.get(i, j) {
+                Cell::Fluid(fs) => (0, fs.density, fs.velocity),
+                Cell::Obstacle => (1, 0., Default::default()),
+            };
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                i, j, pos.0, pos.1, obstacle, density, velocity.0, velocity.1
+            )?;
+        }
+    }
+    writer.flush()
+}
+
+// force of the fluid on every obstacle at every step kept in force_history
+pub fn write_forces(fluid_sim: &LBFluidSim, path: &Path) -> std::io::Result<()> {
+    let mut writer = ForceWriter::create(path)?;
+    let first_step = fluid_sim.step + 1 - fluid_sim.force_history.len();
+    for (step, forces) in (first_step..).zip(&fluid_sim.force_history) {
+        writer.write_forces(step, forces)?;
+    }
+    writer.finish()
+}
+
+// the same rows written as the steps go, so a long run needs no force history
+pub struct ForceWriter {
+    writer: BufWriter<File>,
+}
+
+impl ForceWriter {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "step,obstacle,fx,fy")?;
+        Ok(ForceWriter { writer })
+    }
+
+    // appends to the file of a run restarted at step, the rows of later steps, written before the
+    // run stopped, are dropped, and so is a last row cut short
+    pub fn resume(path: &Path, step: usize) -> std::io::Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Self::create(path),
+            Err(e) => return Err(e),
+        };
+        let mut kept = 0;
+        for line in text.split_inclusive('\n') {
+            let row_step = line.split(',').next().and_then(|s| s.parse::<usize>().ok());
+            if !line.ends_with('\n') || row_step.is_some_and(|row_step| row_step > step) {
+                break;
+            }
+            kept += line.len();
+        }
+        if kept == 0 {
+            return Self::create(path);
+        }
+        let file = OpenOptions::new().append(true).open(path)?;
+        file.set_len(kept as u64)?;
+        Ok(ForceWriter { writer: BufWriter::new(file) })
+    }
+
+    // obstacle_forces after the update that reached step
+    pub fn write_forces(&mut self, step: usize, forces: &[Tup2<f32>]) -> std::io::Result<()> {
+        for (obstacle, force) in forces.iter().enumerate() {
+            writeln!(self.writer, "{},{},{},{}", step, obstacle, force.0, force.1)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_drops_the_rows_after_the_checkpoint() {
+        let path = std::env::temp_dir().join(format!("fluid_{}_forces.csv", std::process::id()));
+        let mut writer = ForceWriter::create(&path).unwrap();
+        for step in 1..=5 {
+            writer.write_forces(step, &[Tup2(step as f32, 0.)]).unwrap();
+        }
+        writer.finish().unwrap();
+        // a run killed while writing the row of step 6
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "6,0,6").unwrap();
+
+        let mut writer = ForceWriter::resume(&path, 3).unwrap();
+        writer.write_forces(4, &[Tup2(-4., 0.)]).unwrap();
+        writer.finish().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "step,obstacle,fx,fy\n1,0,1,0\n2,0,2,0\n3,0,3,0\n4,0,-4,0\n");
+
+        let mut writer = ForceWriter::resume(&path, 9).unwrap();
+        writer.write_forces(10, &[Tup2(10., 0.)]).unwrap();
+        writer.finish().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.ends_with("3,0,3,0\n4,0,-4,0\n10,0,10,0\n"), "{}", text);
+
+        std::fs::remove_file(&path).unwrap();
+        let writer = ForceWriter::resume(&path, 3).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "step,obstacle,fx,fy\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,387 @@
+use crate::tup2::Tup2;
+use std::f32::consts::PI;
+
+// arithmetic in the grid position x, y as written in scenario files, e.g. "0.1 * sin(2 * pi * y)"
+// comparisons give 1 or 0, so a step is "if(y < 0, 1, 0.6)"
+#[derive(Clone, Debug)]
+pub struct Expr {
+    root: Node,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Number(f32),
+    X,
+    Y,
+    Neg(Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+    Compare(&'static str, Box<Node>, Box<Node>),
+    Call(&'static str, Vec<Node>),
+}
+
+const FUNCTIONS: [(&str, usize); 13] = [
+    ("sin", 1),
+    ("cos", 1),
+    ("tan", 1),
+    ("tanh", 1),
+    ("exp", 1),
+    ("ln", 1),
+    ("sqrt", 1),
+    ("abs", 1),
+    ("floor", 1),
+    ("min", 2),
+    ("max", 2),
+    ("atan2", 2),
+    ("if", 3),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut k = 0;
+    while k < chars.len() {
+        let c = chars[k];
+        if c.is_whitespace() {
+            k += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = k;
+            while k < chars.len() && (chars[k].is_ascii_digit() || chars[k] == '.') {
+                k += 1;
+            }
+            // exponent, only when a digit follows so "2e" stays an error instead of eating a name
+            if k < chars.len() && (chars[k] == 'e' || chars[k] == 'E') {
+                let sign = usize::from(k + 1 < chars.len() && matches!(chars[k + 1], '+' | '-'));
+                if k + 1 + sign < chars.len() && chars[k + 1 + sign].is_ascii_digit() {
+                    k += 1 + sign;
+                    while k < chars.len() && chars[k].is_ascii_digit() {
+                        k += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..k].iter().collect();
+            let value = text.parse().map_err(|_| format!("bad number '{}'", text))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = k;
+            while k < chars.len() && (chars[k].is_ascii_alphanumeric() || chars[k] == '_') {
+                k += 1;
+            }
+            tokens.push(Token::Ident(chars[start..k].iter().collect()));
+        } else {
+            let next = chars.get(k + 1).copied();
+            let symbol = match (c, next) {
+                ('<', Some('=')) => "<=",
+                ('>', Some('=')) => ">=",
+                ('+', _) => "+",
+                ('-', _) => "-",
+                ('*', _) => "*",
+                ('/', _) => "/",
+                ('^', _) => "^",
+                ('(', _) => "(",
+                (')', _) => ")",
+                (',', _) => ",",
+                ('<', _) => "<",
+                ('>', _) => ">",
+                _ => return Err(format!("unexpected '{}' in '{}'", c, source)),
+            };
+            k += symbol.len();
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", symbol))
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let lhs = self.additive()?;
+        for op in ["<=", ">=", "<", ">"] {
+            if self.eat(op) {
+                let rhs = self.additive()?;
+                return Ok(Node::Compare(op, Box::new(lhs), Box::new(rhs)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn additive(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        loop {
+            let op = if self.eat("+") {
+                '+'
+            } else if self.eat("-") {
+                '-'
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                '*'
+            } else if self.eat("/") {
+                '/'
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    // -x^2 is -(x^2) and 2^3^2 is 2^(3^2)
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat("-") {
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        let base = self.primary()?;
+        if self.eat("^") {
+            return Ok(Node::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        match token {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Symbol("(") => {
+                let node = self.comparison()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "x" => Ok(Node::X),
+                "y" => Ok(Node::Y),
+                "pi" => Ok(Node::Number(PI)),
+                _ => {
+                    let &(function, arity) = FUNCTIONS
+                        .iter()
+                        .find(|(function, _)| *function == name)
+                        .ok_or_else(|| format!("unknown name '{}'", name))?;
+                    self.expect("(")?;
+                    let mut args = vec![self.comparison()?];
+                    while self.eat(",") {
+                        args.push(self.comparison()?);
+                    }
+                    self.expect(")")?;
+                    if args.len() != arity {
+                        return Err(format!(
+                            "{} expects {} argument(s), got {}",
+                            function,
+                            arity,
+                            args.len()
+                        ));
+                    }
+                    Ok(Node::Call(function, args))
+                }
+            },
+            Token::Symbol(symbol) => Err(format!("unexpected '{}'", symbol)),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let root = parser
+            .comparison()
+            .map_err(|e| format!("{} in '{}'", e, source))?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!("trailing input in '{}'", source));
+        }
+        Ok(Expr { root })
+    }
+
+    pub fn eval(&self, pos: Tup2<f32>) -> f32 {
+        Self::eval_node(&self.root, pos)
+    }
+
+    fn eval_node(node: &Node, pos: Tup2<f32>) -> f32 {
+        let eval = |node: &Node| Self::eval_node(node, pos);
+        match node {
+            Node::Number(value) => *value,
+            Node::X => pos.0,
+            Node::Y => pos.1,
+            Node::Neg(node) => -eval(node),
+            Node::Binary(op, lhs, rhs) => {
+                let (a, b) = (eval(lhs), eval(rhs));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            }
+            Node::Compare(op, lhs, rhs) => {
+                let (a, b) = (eval(lhs), eval(rhs));
+                let holds = match *op {
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    _ => a >= b,
+                };
+                if holds {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Node::Call(function, args) => {
+                let a = eval(&args[0]);
+                match *function {
+                    "sin" => a.sin(),
+                    "cos" => a.cos(),
+                    "tan" => a.tan(),
+                    "tanh" => a.tanh(),
+                    "exp" => a.exp(),
+                    "ln" => a.ln(),
+                    "sqrt" => a.sqrt(),
+                    "abs" => a.abs(),
+                    "floor" => a.floor(),
+                    "min" => a.min(eval(&args[1])),
+                    "max" => a.max(eval(&args[1])),
+                    "atan2" => a.atan2(eval(&args[1])),
+                    // only the chosen branch is evaluated
+                    _ => {
+                        if a != 0. {
+                            eval(&args[1])
+                        } else {
+                            eval(&args[2])
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> f32 {
+        Expr::parse(source).unwrap().eval(Tup2(3., -2.))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.);
+        assert_eq!(eval("(1 + 2) * 3"), 9.);
+        assert_eq!(eval("8 / 4 / 2"), 1.);
+        assert_eq!(eval("7 - 2 - 1"), 4.);
+        assert_eq!(eval("2 * 3 ^ 2"), 18.);
+        assert_eq!(eval("1 + 1 < 3"), 1.);
+        assert_eq!(eval("x * y"), -6.);
+    }
+
+    #[test]
+    fn negation_binds_looser_than_power() {
+        assert_eq!(eval("-x^2"), -9.);
+        assert_eq!(eval("(-x)^2"), 9.);
+        assert_eq!(eval("2^-1"), 0.5);
+        assert_eq!(eval("--x"), 3.);
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(eval("2^3^2"), 512.);
+        assert_eq!(eval("(2^3)^2"), 64.);
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(eval("1e-3"), 1e-3);
+        assert_eq!(eval("2.5E+2"), 250.);
+        assert_eq!(eval(".5"), 0.5);
+        assert_eq!(eval("1e3 * x"), 3000.);
+        assert!(Expr::parse("2e").is_err());
+        assert!(Expr::parse("2ex").is_err());
+        assert!(Expr::parse("1.2.3").is_err());
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("min(x, y)"), -2.);
+        assert_eq!(eval("max(x, y)"), 3.);
+        assert_eq!(eval("abs(y)"), 2.);
+        assert_eq!(eval("floor(-0.5)"), -1.);
+        assert_eq!(eval("sin(0) + cos(0)"), 1.);
+    }
+
+    #[test]
+    fn arity_errors() {
+        let error = Expr::parse("min(1)").unwrap_err();
+        assert!(error.contains("min expects 2 argument(s), got 1"), "{}", error);
+        assert!(Expr::parse("sin(1, 2)").is_err());
+        assert!(Expr::parse("if(1, 2)").is_err());
+        assert!(Expr::parse("sin()").is_err());
+        assert!(Expr::parse("sin 1").is_err());
+    }
+
+    #[test]
+    fn trailing_input() {
+        let error = Expr::parse("1 2").unwrap_err();
+        assert!(error.contains("trailing input"), "{}", error);
+        assert!(Expr::parse("x)").is_err());
+        assert!(Expr::parse("1 < 2 < 3").is_err());
+    }
+
+    #[test]
+    fn other_errors() {
+        assert!(Expr::parse("").is_err());
+        assert!(Expr::parse("(1 + 2").is_err());
+        assert!(Expr::parse("z").is_err());
+        assert!(Expr::parse("1 # 2").is_err());
+        assert!(Expr::parse("1 +").is_err());
+    }
+
+    #[test]
+    fn if_evaluates_only_the_chosen_branch() {
+        assert_eq!(eval("if(x > 0, 1, 0.6)"), 1.);
+        assert_eq!(eval("if(y > 0, 1, 0.6)"), 0.6);
+        // the other branch would be NaN
+        assert_eq!(eval("if(1, 2, ln(-1))"), 2.);
+        assert_eq!(eval("if(0, sqrt(-1), 3)"), 3.);
+    }
+}
@@ -0,0 +1,442 @@
+use crate::fluid_struct::LBFluidSim;
+use crate::lattice_state::{Cell, FluidState};
+use crate::obstacle_board::obstacle_index;
+use crate::tup2::Tup2;
+use ndarray::{Array2, Axis, Zip};
+use rayon::prelude::*;
+
+// macroscopic fields of the current state as plain arrays for the writers,
+// obstacle cells hold zeros
+
+pub fn density_field(fluid_sim: &LBFluidSim) -> Array2<f32> {
+    fluid_sim.lattice.density.clone()
+}
+
+pub fn velocity_field(fluid_sim: &LBFluidSim) -> Array2<Tup2<f32>> {
+    Array2::from_shape_fn(fluid_sim.shape, |(i, j)| fluid_sim.lattice.velocity(i, j))
+}
+
+pub fn obstacle_mask(fluid_sim: &LBFluidSim) -> Array2<bool> {
+    fluid_sim.lattice.obstacle.clone()
+}
+
+// state of the fluid cell at (i, j) shifted by (di, dj), wrapping around periodic edges
+fn neighbor_state(
+    fluid_sim: &LBFluidSim,
+    i: usize,
+    j: usize,
+    di: isize,
+    dj: isize,
+) -> Option<FluidState> {
+    let (nx, ny) = fluid_sim.shape;
+    let wrap = |k: usize, dk: isize, n: usize, periodic: bool| {
+        let k = k as isize + dk;
+        if periodic {
+            Some(k.rem_euclid(n as isize) as usize)
+        } else {
+            (0..n as isize).contains(&k).then_some(k as usize)
+        }
+    };
+    let ni = wrap(i, di, nx, fluid_sim.boundaries.left.is_periodic())?;
+    let nj = wrap(j, dj, ny, fluid_sim.boundaries.bottom.is_periodic())?;
+    fluid_sim.lattice.fluid_state(ni, nj)
+}
+
+// derivative of a quantity of the fluid along (di, dj) at the fluid cell (i, j): central where
+// both neighbors are fluid, second order one-sided next to an obstacle or a non-periodic edge,
+// first order when the fluid is only one cell deep, zero with no fluid neighbor at all
+fn derivative(
+    fluid_sim: &LBFluidSim,
+    i: usize,
+    j: usize,
+    (di, dj): (isize, isize),
+    value: impl Fn(&FluidState) -> f32,
+) -> f32 {
+    let at = |k: isize| neighbor_state(fluid_sim, i, j, k * di, k * dj).map(|fs| value(&fs));
+    let Some(center) = at(0) else {
+        return 0.;
+    };
+    match (at(-1), at(1)) {
+        (Some(back), Some(front)) => 0.5 * (front - back),
+        (None, Some(front)) => match at(2) {
+            Some(front_2) => 0.5 * (-3. * center + 4. * front - front_2),
+            None => front - center,
+        },
+        (Some(back), None) => match at(-2) {
+            Some(back_2) => 0.5 * (3. * center - 4. * back + back_2),
+            None => center - back,
+        },
+        (None, None) => 0.,
+    }
+}
+
+// dfy/dx - dfx/dy of a vector quantity of the fluid, zero on obstacles
+fn curl(fluid_sim: &LBFluidSim, field: fn(&FluidState) -> Tup2<f32>) -> Array2<f32> {
+    Array2::from_shape_fn(fluid_sim.shape, |(i, j)| {
+        derivative(fluid_sim, i, j, (1, 0), |fs| field(fs).1)
+            - derivative(fluid_sim, i, j, (0, 1), |fs| field(fs).0)
+    })
+}
+
+// duy/dx - dux/dy in lattice units
+pub fn vorticity(fluid_sim: &LBFluidSim) -> Array2<f32> {
+    curl(fluid_sim, |fs| fs.velocity)
+}
+
+// stream function of the mass flux, density * (ux, uy) = (dpsi/dy, -dpsi/dx) in lattice units,
+// which unlike the velocity has no divergence in a steady flow of the slightly compressible
+// lattice fluid, so the contours of psi are its streamlines. It solves the Poisson equation
+// laplacian(psi) = -curl(density * u) by red-black successive over-relaxation on the whole grid,
+// until the largest residual has dropped by TOLERANCE.
+// Non-periodic edges and obstacles at rest take psi from the flux between them, with
+// psi(0, 0) = 0, the cells of moving obstacles count as fluid going with their wall. Along a
+// periodic direction psi grows by the net flow across it, solved for as a periodic part plus a
+// linear term with the mean flux.
+pub fn stream_function(fluid_sim: &LBFluidSim) -> Array2<f32> {
+    const TOLERANCE: f64 = 1e-7; // of the largest residual relative to the one of the start
+    let (nx, ny) = fluid_sim.shape;
+    let periodic_x = fluid_sim.boundaries.left.is_periodic();
+    let periodic_y = fluid_sim.boundaries.bottom.is_periodic();
+    // moving obstacles at unit density
+    let flux = Array2::from_shape_fn((nx, ny), |(i, j)| {
+        let flux = match fluid_sim.lattice.get(i, j) {
+            Cell::Fluid(fs) => fs.velocity * fs.density,
+            Cell::Obstacle => fluid_sim.obstacle_board.get_wall_velocity(i, j),
+        };
+        (flux.0 as f64, flux.1 as f64)
+    });
+    let flux_x = |i: usize, j: usize| flux[[i, j]].0;
+    let flux_y = |i: usize, j: usize| flux[[i, j]].1;
+    // mean flux across the periodic directions, whose net flow psi has to grow by
+    let mean = |periodic: bool, component: fn(&(f64, f64)) -> f64| match periodic {
+        true => flux.iter().map(component).sum::<f64>() / (nx * ny) as f64,
+        false => 0.,
+    };
+    let (mean_x, mean_y) = (mean(periodic_y, |flux| flux.0), mean(periodic_x, |flux| flux.1));
+    // psi minus its periodic part
+    let linear = |(i, j): (usize, usize)| mean_x * j as f64 - mean_y * i as f64;
+
+    // neighbors of interior cells, across the edge when periodic
+    let east = |i: usize| if i + 1 == nx { 0 } else { i + 1 };
+    let west = |i: usize| if i == 0 { nx - 1 } else { i - 1 };
+    let north = |j: usize| if j + 1 == ny { 0 } else { j + 1 };
+    let south = |j: usize| if j == 0 { ny - 1 } else { j - 1 };
+    let curl = curl(fluid_sim, |fs| fs.velocity * fs.density);
+    let mut source = Array2::from_shape_fn((nx, ny), |(i, j)| match fluid_sim.lattice.get(i, j) {
+        Cell::Fluid(_) => curl[[i, j]] as f64,
+        Cell::Obstacle => {
+            let dy_dx = flux_y(east(i), j) - flux_y(west(i), j);
+            let dx_dy = flux_x(i, north(j)) - flux_x(i, south(j));
+            0.5 * (dy_dx - dx_dy)
+        }
+    });
+
+    // flux crossing the steps of a path of cells, trapezoid rule, steps may cross a periodic edge
+    let trace = |path: &[(usize, usize)], start: f64| {
+        let mut values = vec![start];
+        for step in path.windows(2) {
+            let ((i0, j0), (i1, j1)) = (step[0], step[1]);
+            let delta = |from: usize, to: usize| match to as isize - from as isize {
+                d if d.abs() > 1 => -d.signum() as f64,
+                d => d as f64,
+            };
+            let u = 0.5 * (flux_x(i0, j0) + flux_x(i1, j1));
+            let v = 0.5 * (flux_y(i0, j0) + flux_y(i1, j1));
+            let last = values[values.len() - 1];
+            values.push(last + u * delta(j0, j1) - v * delta(i0, i1));
+        }
+        values
+    };
+    // around a closed path the flux does not add up to zero exactly, the lattice fluid being
+    // slightly compressible and the net flow along a periodic direction being in the linear
+    // term, the difference is spread evenly along the path
+    let close = |mut values: Vec<f64>| {
+        let steps = values.len() - 1;
+        let mismatch = values[steps] - values[0];
+        for (k, value) in values.iter_mut().enumerate() {
+            *value -= mismatch * k as f64 / steps as f64;
+        }
+        values
+    };
+    let row = |j: usize| (0..nx).chain([0]).map(move |i| (i, j)).collect::<Vec<_>>();
+    let column = |i: usize| (0..ny).chain([0]).map(move |j| (i, j)).collect::<Vec<_>>();
+
+    // fixed values on the non-periodic edges
+    let mut psi = Array2::<f64>::zeros((nx, ny));
+    let mut fixed = Array2::from_elem((nx, ny), false);
+    let mut edges = Vec::new();
+    match (periodic_x, periodic_y) {
+        (false, false) => {
+            // counterclockwise around the whole domain
+            let path: Vec<_> = (0..nx)
+                .map(|i| (i, 0))
+                .chain((1..ny).map(|j| (nx - 1, j)))
+                .chain((0..nx - 1).rev().map(|i| (i, ny - 1)))
+                .chain((0..ny - 1).rev().map(|j| (0, j)))
+                .collect();
+            edges.push((path.clone(), close(trace(&path, 0.))));
+        }
+        (true, false) => {
+            let top = trace(&column(0)[..ny], 0.)[ny - 1];
+            edges.push((row(0), close(trace(&row(0), 0.))));
+            edges.push((row(ny - 1), close(trace(&row(ny - 1), top))));
+        }
+        (false, true) => {
+            let right = trace(&row(0)[..nx], 0.)[nx - 1];
+            edges.push((column(0), close(trace(&column(0), 0.))));
+            edges.push((column(nx - 1), close(trace(&column(nx - 1), right))));
+        }
+        (true, true) => {
+            // the periodic problem only has a solution for a source of zero mean
+            let mean = source.mean().unwrap_or(0.);
+            source -= mean;
+            fixed[[0, 0]] = true;
+        }
+    }
+    for (path, values) in edges {
+        for ((i, j), value) in path.into_iter().zip(values) {
+            psi[[i, j]] = value;
+            fixed[[i, j]] = true;
+        }
+    }
+
+    // an obstacle at rest is a streamline, psi is constant over it with the value reached by
+    // the flux from a known psi to its first cell, moving obstacles are solved for like fluid
+    let board = &fluid_sim.obstacle_board;
+    let count = fluid_sim.obstacle_sizes.len();
+    let mut first_cell = vec![None; count];
+    let mut moving = vec![false; count];
+    for ((i, j), label) in fluid_sim.obstacle_labels.indexed_iter() {
+        if let Some(label) = obstacle_index(*label) {
+            first_cell[label].get_or_insert((i, j));
+            moving[label] |= board.wall_velocity.contains_key(&board.get_index(i, j));
+        }
+    }
+    let obstacle_psi: Vec<Option<f64>> = first_cell
+        .iter()
+        .zip(&moving)
+        .map(|(first_cell, moving)| {
+            let (i, j) = (*first_cell)?;
+            let path: Vec<_> = if !periodic_y {
+                (0..=j).map(|j| (i, j)).collect()
+            } else if !periodic_x {
+                (0..=i).map(|i| (i, j)).collect()
+            } else {
+                (0..=i).map(|i| (i, 0)).chain((1..=j).map(|j| (i, j))).collect()
+            };
+            let start = psi[[path[0].0, path[0].1]] + linear(path[0]);
+            (!moving).then(|| trace(&path, start)[path.len() - 1] - linear((i, j)))
+        })
+        .collect();
+    for ((i, j), label) in fluid_sim.obstacle_labels.indexed_iter() {
+        if let Some(value) = obstacle_index(*label).and_then(|label| obstacle_psi[label]) {
+            if !fixed[[i, j]] {
+                psi[[i, j]] = value;
+                fixed[[i, j]] = true;
+            }
+        }
+    }
+
+    // red-black ordering, a cell only reads cells of other colors, so the cells of one color are
+    // updated in parallel. A periodic edge of odd length would make the last column or row a
+    // neighbor of the first of the same color, they get colors of their own
+    let odd_x = periodic_x && nx % 2 == 1;
+    let odd_y = periodic_y && ny % 2 == 1;
+    let last = [(false, false), (true, false), (false, true), (true, true)];
+    let colors: Vec<(usize, bool, bool)> = (last.into_iter())
+        .filter(|(last_i, last_j)| (!last_i || odd_x) && (!last_j || odd_y))
+        .flat_map(|(last_i, last_j)| [(0, last_i, last_j), (1, last_i, last_j)])
+        .collect();
+    // rows of the cells of column i with a color, which is the parity of i + j and whether it is
+    // the color of the last column and of the last row
+    let cells = |i: usize, (parity, last_i, last_j): (usize, bool, bool)| {
+        let in_column = !odd_x || (i == nx - 1) == last_i;
+        ((i + parity) % 2..ny)
+            .step_by(2)
+            .filter(move |j| in_column && (!odd_y || (*j == ny - 1) == last_j))
+    };
+    let residual = |psi: &Array2<f64>, i: usize, j: usize| {
+        let neighbors =
+            psi[[east(i), j]] + psi[[west(i), j]] + psi[[i, north(j)]] + psi[[i, south(j)]];
+        neighbors + source[[i, j]] - 4. * psi[[i, j]]
+    };
+    let largest_residual = |psi: &Array2<f64>| {
+        (0..nx)
+            .into_par_iter()
+            .map(|i| {
+                (0..ny)
+                    .filter(|j| !fixed[[i, *j]])
+                    .fold(0f64, |largest, j| largest.max(residual(psi, i, j).abs()))
+            })
+            .reduce(|| 0., f64::max)
+    };
+
+    let relaxation = 2. / (1. + (std::f64::consts::PI / nx.max(ny) as f64).sin());
+    let initial_residual = largest_residual(&psi);
+    let mut update = Array2::<f64>::zeros((nx, ny));
+    for sweep in 0..20 * nx.max(ny) {
+        for &color in &colors {
+            let updates = update.axis_iter_mut(Axis(0)).into_par_iter().enumerate();
+            updates.for_each(|(i, mut update)| {
+                for j in cells(i, color).filter(|j| !fixed[[i, *j]]) {
+                    update[j] = 0.25 * relaxation * residual(&psi, i, j);
+                }
+            });
+            // fixed cells keep an update of zero
+            let columns = psi.axis_iter_mut(Axis(0)).into_par_iter().zip(update.axis_iter(Axis(0)));
+            columns.enumerate().for_each(|(i, (mut psi, update))| {
+                for j in cells(i, color) {
+                    psi[j] += update[j];
+                }
+            });
+        }
+        // as costly as a sweep, so only every few
+        if sweep % 10 == 9 && largest_residual(&psi) <= TOLERANCE * initial_residual {
+            break;
+        }
+    }
+    Array2::from_shape_fn((nx, ny), |(i, j)| (psi[[i, j]] + linear((i, j))) as f32)
+}
+
+// the scalar quantities the viewer can color the lattice by, in lattice units
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalarField {
+    Density,
+    Speed,
+    VelocityX,
+    VelocityY,
+    Vorticity,
+    Pressure,
+}
+
+impl ScalarField {
+    pub const ALL: [ScalarField; 6] = [
+        ScalarField::Density,
+        ScalarField::Speed,
+        ScalarField::VelocityX,
+        ScalarField::VelocityY,
+        ScalarField::Vorticity,
+        ScalarField::Pressure,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScalarField::Density => "density",
+            ScalarField::Speed => "speed",
+            ScalarField::VelocityX => "ux",
+            ScalarField::VelocityY => "uy",
+            ScalarField::Vorticity => "vorticity",
+            ScalarField::Pressure => "pressure",
+        }
+    }
+
+    // takes both signs around a meaningful zero
+    pub fn is_signed(self) -> bool {
+        matches!(self, ScalarField::VelocityX | ScalarField::VelocityY | ScalarField::Vorticity)
+    }
+
+    // zero on obstacles
+    pub fn compute(self, fluid_sim: &LBFluidSim) -> Array2<f32> {
+        let lattice = &fluid_sim.lattice;
+        match self {
+            ScalarField::Density => lattice.density.clone(),
+            ScalarField::Speed => Zip::from(&lattice.ux)
+                .and(&lattice.uy)
+                .map_collect(|ux, uy| Tup2(*ux, *uy).mag()),
+            ScalarField::VelocityX => lattice.ux.clone(),
+            ScalarField::VelocityY => lattice.uy.clone(),
+            ScalarField::Vorticity => vorticity(fluid_sim),
+            // p = c_s^2 density with c_s^2 = 1/3
+            ScalarField::Pressure => lattice.density.map(|density| density / 3.),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::{BoundaryConfig, EdgeBoundary};
+    use crate::obstacle_board::{GridStats, ObstacleBoard};
+    use crate::scenario::build_scenario;
+
+    // cells one apart, positions measured from the middle of the grid
+    fn board(shape: (usize, usize)) -> ObstacleBoard {
+        let length = (shape.0.max(shape.1) - 1) as f32;
+        ObstacleBoard::new_empty(GridStats::new(shape, length))
+    }
+
+    #[test]
+    fn stream_function_does_not_depend_on_the_thread_count() {
+        let mut cylinder = build_scenario("cylinder", (40, 20), 0.05).unwrap();
+        // periodic edges of odd length, where the last column and row get colors of their own
+        let mut periodic = build_scenario("cylinder", (31, 17), 0.05).unwrap();
+        let edge = EdgeBoundary::Periodic;
+        periodic.set_boundaries(BoundaryConfig::new(edge, edge, edge, edge));
+        for fluid_sim in [&mut cylinder, &mut periodic] {
+            for _ in 0..50 {
+                fluid_sim.update();
+            }
+            let psi = |threads: usize| {
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                pool.install(|| stream_function(fluid_sim))
+            };
+            let serial = psi(1);
+            assert!(serial.iter().all(|value| value.is_finite()));
+            assert!(serial.iter().any(|value| *value != 0.));
+            let bits = |psi: &Array2<f32>| psi.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+            assert!(bits(&serial) == bits(&psi(4)));
+        }
+    }
+
+    #[test]
+    fn stream_function_gradient_is_the_mass_flux() {
+        use EdgeBoundary::{Periodic, Wall};
+        // walls half a cell outside the edge cells
+        fn parabola(x: f32) -> f32 {
+            0.05 * (1. - (x / 8.).powi(2))
+        }
+        let flow = |shape, boundaries, velocity: fn(Tup2<f32>) -> Tup2<f32>| {
+            LBFluidSim::new(board(shape), |_| 1.02, velocity, 0.1, boundaries)
+        };
+        let runs = [
+            flow((24, 16), BoundaryConfig::new(Periodic, Periodic, Wall, Wall), |pos| {
+                Tup2(parabola(pos.1), 0.)
+            }),
+            flow((16, 24), BoundaryConfig::new(Wall, Wall, Periodic, Periodic), |pos| {
+                Tup2(0., parabola(pos.0))
+            }),
+            flow((16, 12), BoundaryConfig::new(Periodic, Periodic, Periodic, Periodic), |_| {
+                Tup2(0.04, -0.03)
+            }),
+        ];
+        for fluid_sim in runs {
+            let (shape, boundaries) = (fluid_sim.shape, fluid_sim.boundaries);
+            let psi = stream_function(&fluid_sim);
+            for i in 1..shape.0 - 1 {
+                for j in 1..shape.1 - 1 {
+                    let gradient = Tup2(
+                        0.5 * (psi[[i + 1, j]] - psi[[i - 1, j]]),
+                        0.5 * (psi[[i, j + 1]] - psi[[i, j - 1]]),
+                    );
+                    let flux = fluid_sim.lattice.velocity(i, j) * 1.02;
+                    let error = (gradient - Tup2(-flux.1, flux.0)).mag();
+                    let context = format!("{:?}, cell ({}, {})", boundaries, i, j);
+                    assert!(error < 1e-3, "{}: off by {}", context, error);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn vorticity_of_solid_rotation_is_twice_the_rotation_rate() {
+        let rate = 0.002;
+        let mut board = board((20, 16));
+        board.set_line(Tup2(-3., -2.), Tup2(4., 1.));
+        let rotation = |pos: Tup2<f32>| Tup2(-pos.1, pos.0) * rate;
+        let fluid_sim = LBFluidSim::new(board, |_| 1., rotation, 0.1, BoundaryConfig::walls());
+        for ((i, j), vorticity) in vorticity(&fluid_sim).indexed_iter() {
+            let expected = if fluid_sim.lattice.is_obstacle(i, j) { 0. } else { 2. * rate };
+            assert!((vorticity - expected).abs() < 1e-6, "cell ({}, {}): {}", i, j, vorticity);
+        }
+    }
+}
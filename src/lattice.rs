@@ -0,0 +1,87 @@
+use crate::lattice_state::{Cell, FluidState};
+use crate::tup2::Tup2;
+use ndarray::{Array2, Array3};
+
+// the state of the whole grid as structure of arrays: the populations of each direction in one
+// contiguous (nx, ny) block, d_i[[q, i, j]], the density and velocity of the last update and the
+// obstacle mask, obstacle cells hold zeros but in the pushed phase of the AA kernel. Cell and
+// FluidState are built on demand by get and fluid_state for code that works cell by cell.
+#[derive(Clone, Debug)]
+pub struct Lattice {
+    pub d_i: Array3<f32>,
+    pub density: Array2<f32>,
+    pub ux: Array2<f32>,
+    pub uy: Array2<f32>,
+    pub obstacle: Array2<bool>,
+}
+
+impl Lattice {
+    pub fn new(obstacle: Array2<bool>) -> Self {
+        let shape = obstacle.dim();
+        Lattice {
+            d_i: Array3::zeros((9, shape.0, shape.1)),
+            density: Array2::zeros(shape),
+            ux: Array2::zeros(shape),
+            uy: Array2::zeros(shape),
+            obstacle,
+        }
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        self.obstacle.dim()
+    }
+
+    // position of (i, j) within the block of one direction
+    #[inline]
+    pub fn offset(&self, i: usize, j: usize) -> usize {
+        i * self.obstacle.dim().1 + j
+    }
+
+    #[inline]
+    pub fn is_obstacle(&self, i: usize, j: usize) -> bool {
+        self.obstacle[[i, j]]
+    }
+
+    #[inline]
+    pub fn velocity(&self, i: usize, j: usize) -> Tup2<f32> {
+        Tup2(self.ux[[i, j]], self.uy[[i, j]])
+    }
+
+    #[inline]
+    pub fn fluid_state(&self, i: usize, j: usize) -> Option<FluidState> {
+        if self.obstacle[[i, j]] {
+            return None;
+        }
+        let (cells, k) = (self.obstacle.len(), self.offset(i, j));
+        let populations = self.d_i.as_slice().expect("populations are contiguous");
+        let d_i = std::array::from_fn(|q| populations[q * cells + k]);
+        Some(FluidState::new(d_i, self.velocity(i, j), self.density[[i, j]]))
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> Cell {
+        self.fluid_state(i, j).map_or(Cell::Obstacle, Cell::Fluid)
+    }
+
+    // stores a fluid cell, the obstacle mask is left as it is
+    #[inline]
+    pub fn set(&mut self, i: usize, j: usize, fluid_state: &FluidState) {
+        self.density[[i, j]] = fluid_state.density;
+        self.ux[[i, j]] = fluid_state.velocity.0;
+        self.uy[[i, j]] = fluid_state.velocity.1;
+        let (cells, k) = (self.obstacle.len(), self.offset(i, j));
+        let populations = self.d_i.as_slice_mut().expect("populations are contiguous");
+        for q in 0..9 {
+            populations[q * cells + k] = fluid_state.d_i[q];
+        }
+    }
+
+    // every cell in the order of Array2::iter, j running fastest
+    pub fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        let (nx, ny) = self.shape();
+        (0..nx).flat_map(move |i| (0..ny).map(move |j| self.get(i, j)))
+    }
+
+    pub fn map<T>(&self, f: impl Fn(Cell) -> T) -> Array2<T> {
+        Array2::from_shape_fn(self.shape(), |(i, j)| f(self.get(i, j)))
+    }
+}
@@ -6,8 +6,14 @@ use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::Color;
 use std::time::Duration;
+use crate::boundary::{BoundaryConfig, EdgeBoundary};
+use crate::color::ColorRange;
+use crate::fields::ScalarField;
 use crate::fluid_struct::LBFluidSim;
+use crate::lattice_state::{viscosity_from_tau, DEFAULT_TAU};
 use crate::obstacle_board::{GridStats, ObstacleBoard};
+use crate::scenario_file::ScenarioFile;
+use std::path::Path;
 
 pub fn main() {
     let sdl_context = sdl2::init().unwrap();
@@ -27,56 +33,27 @@ pub fn main() {
     let scene2d_list = vec![Scene2D::new(800., Vec2::new(0., 0.))];
     let mut canvas2d = Canvas2D::new(canvas, size, 1., Vec2::new(0., 0.), (0, 0, 0), scene2d_list);
 
-    const LENGTH: f32 = 1.;
-    let grid_stats = GridStats::new((200, 100), LENGTH);
-    let mut obstacle_board = ObstacleBoard::new_empty(grid_stats.clone());
-    // obstacle_board.set_piecewise_curve(
-    //     vec![Tup2(-0.3, 0.03), Tup2(0.3, 0.03)]
-    // );
-    // obstacle_board.set_piecewise_curve(
-    //     vec![Tup2(-0.3, -0.03), Tup2(0.3, -0.03)]
-    // );
-    // obstacle_board.set_piecewise_curve(
-    //     vec![Tup2(0., 0.2), Tup2(0.3, 0.), Tup2(0., -0.3), Tup2(-0.3, 0.), Tup2(0., 0.3)]
-    // );
-    obstacle_board.set_piecewise_curve(
-        vec![Tup2(0., 0.1), Tup2(0., -0.1)]
-    );
-    const MAX_DENSITY: f32= 1.;
-    fn f_density(pos: Tup2<f32>) -> f32{
-        // 1.
-        // // (0.55 * LENGTH + 0.7 * pos.0) * MAX_DENSITY
-        1.1- (0.1 * pos.0 + 0.5)
-        // if pos.0.abs() > 0.1{
-        //     0.1
-        // }
-        // else{
-        //     0.5
-        // }
-        // if pos.1 < 0.07 * (5. * pos.0).sin(){
-        //     1.
-        // }
-        // else{
-        //     0.6
-        // }
-    }
-
-    fn f_velocity(pos: Tup2<f32>) -> Tup2<f32>{
-        Tup2(0.1, 0.)
-        // // Tup2(0., 0.)
-        // Tup2(pos.1, -pos.0).normalize() * 0.1
-          // Tup2(pos.0.clamp(-0.2, 0.2), 0.)
-        // if pos.1 < 0.{
-        //     Tup2(0.5, 0.)
-        // }
-        // else{
-        //     Tup2(0., 0.)
-        // }
-    }
-
-    let mut fluid_sim = LBFluidSim::new(obstacle_board, f_density, f_velocity);
-
-    let mut T = 0;
+    // fluid path/to/scenario.toml runs the file, without an argument the demo below runs forever
+    let (mut fluid_sim, steps) = match std::env::args().nth(1) {
+        Some(path) => {
+            let scenario = ScenarioFile::load(Path::new(&path)).unwrap_or_else(|e| exit_with(&e));
+            let fluid_sim = scenario.build().unwrap_or_else(|e| exit_with(&e));
+            (fluid_sim, scenario.steps)
+        }
+        None => (demo(), usize::MAX),
+    };
+    let grid_stats = fluid_sim.obstacle_board.grid_stats.clone();
+    // keys 1 to 6 pick the field, c the colormap, a freezes or frees the color range
+    let field_keys = [
+        Keycode::Num1,
+        Keycode::Num2,
+        Keycode::Num3,
+        Keycode::Num4,
+        Keycode::Num5,
+        Keycode::Num6,
+    ];
+    let mut shown_range = (0., 1.);
+    let mut title = String::new();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
     'running: loop {
@@ -102,27 +79,128 @@ pub fn main() {
                     }
                     canvas2d.canvas_zoom *= 0.8
                 }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if field_keys.contains(&keycode) => {
+                    let index = field_keys.iter().position(|key| *key == keycode).unwrap();
+                    canvas2d.select_field(ScalarField::ALL[index]);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::C),
+                    ..
+                } => canvas2d.colormap = canvas2d.colormap.next(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::A),
+                    ..
+                } => {
+                    canvas2d.color_range = match canvas2d.color_range {
+                        ColorRange::Auto => ColorRange::Fixed(shown_range.0, shown_range.1),
+                        ColorRange::Fixed(..) => ColorRange::Auto,
+                    }
+                }
                 _ => {}
             }
         }
 
         canvas2d.draw_axis_s2(0.5, 0);
 
-        fluid_sim.update();
+        if fluid_sim.step < steps {
+            fluid_sim.update();
+        }
 
 
-        T+= 1;
-        // println!("{}", T);
+        // println!("{}", fluid_sim.step);
 
 
-        canvas2d.draw_fluid_density(&grid_stats, &fluid_sim, MAX_DENSITY, 0);
+        shown_range = canvas2d.draw_fluid_field(&grid_stats, &fluid_sim, 0);
         canvas2d.draw_fluid_velocity(&grid_stats, &fluid_sim, 0.1, 4, 0);
 
         canvas2d
             .canvas
             .set_draw_color(Color::RGBA(70, 234, 255, 255));
 
+        let range_kind = match canvas2d.color_range {
+            ColorRange::Auto => "auto",
+            ColorRange::Fixed(..) => "fixed",
+        };
+        let new_title = format!(
+            "{} [{:.3e}, {:.3e}] {}, {} - step {}",
+            canvas2d.field.name(),
+            shown_range.0,
+            shown_range.1,
+            range_kind,
+            canvas2d.colormap.name(),
+            fluid_sim.step
+        );
+        if new_title != title {
+            title = new_title;
+            canvas2d.canvas.window_mut().set_title(&title).unwrap();
+        }
+
         canvas2d.canvas.present();
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
 }
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1)
+}
+
+fn demo() -> LBFluidSim {
+    const LENGTH: f32 = 1.;
+    let grid_stats = GridStats::new((200, 100), LENGTH);
+    let mut obstacle_board = ObstacleBoard::new_empty(grid_stats);
+    // obstacle_board.set_piecewise_curve(
+    //     vec![Tup2(-0.3, 0.03), Tup2(0.3, 0.03)]
+    // );
+    // obstacle_board.set_piecewise_curve(
+    //     vec![Tup2(-0.3, -0.03), Tup2(0.3, -0.03)]
+    // );
+    // obstacle_board.set_piecewise_curve(
+    //     vec![Tup2(0., 0.2), Tup2(0.3, 0.), Tup2(0., -0.3), Tup2(-0.3, 0.), Tup2(0., 0.3)]
+    // );
+    obstacle_board.set_piecewise_curve(
+        vec![Tup2(0., 0.1), Tup2(0., -0.1)]
+    );
+    fn f_density(pos: Tup2<f32>) -> f32{
+        // 1.
+        // // (0.55 * LENGTH + 0.7 * pos.0) * MAX_DENSITY
+        1.1- (0.1 * pos.0 + 0.5)
+        // if pos.0.abs() > 0.1{
+        //     0.1
+        // }
+        // else{
+        //     0.5
+        // }
+        // if pos.1 < 0.07 * (5. * pos.0).sin(){
+        //     1.
+        // }
+        // else{
+        //     0.6
+        // }
+    }
+
+    fn f_velocity(_pos: Tup2<f32>) -> Tup2<f32>{
+        Tup2(0.1, 0.)
+        // // Tup2(0., 0.)
+        // Tup2(pos.1, -pos.0).normalize() * 0.1
+          // Tup2(pos.0.clamp(-0.2, 0.2), 0.)
+        // if pos.1 < 0.{
+        //     Tup2(0.5, 0.)
+        // }
+        // else{
+        //     Tup2(0., 0.)
+        // }
+    }
+
+    let viscosity = viscosity_from_tau(DEFAULT_TAU);
+    // inflow at the initial velocity on the left, outflow at the initial density of the right edge
+    let boundaries = BoundaryConfig::inlet_outlet(
+        f_velocity(Tup2(-0.5, 0.)),
+        f_density(Tup2(0.5, 0.)),
+        EdgeBoundary::Periodic,
+    );
+    LBFluidSim::new(obstacle_board, f_density, f_velocity, viscosity, boundaries)
+}
@@ -6,10 +6,10 @@ const WEIGHTS: [f32; 9] = [
     W_REST, W_CARDINAL, W_CARDINAL, W_CARDINAL, W_CARDINAL, W_DIAGONAL, W_DIAGONAL, W_DIAGONAL,
     W_DIAGONAL,
 ];
-const C: f32 = 1. / 1.732_050;
-const C2: f32 = C * C;
+const C: f32 = 1. / 1.732_05;
+pub(crate) const C2: f32 = C * C;
 const C4: f32 = C2 * C2;
-pub(crate) const E_I: [Tup2<f32>; 9] = [
+pub const E_I: [Tup2<f32>; 9] = [
     Tup2(0., 0.),
     Tup2(1., 0.),
     Tup2(0., 1.),
@@ -21,7 +21,7 @@ pub(crate) const E_I: [Tup2<f32>; 9] = [
     Tup2(1., -1.),
 ];
 
-pub(crate) const DISCRETE_VEL: [Tup2<f32>; 9] = [
+pub const DISCRETE_VEL: [Tup2<f32>; 9] = [
     Tup2(0., 0.),
     Tup2(C, 0.),
     Tup2(0., C),
@@ -32,10 +32,64 @@ pub(crate) const DISCRETE_VEL: [Tup2<f32>; 9] = [
     Tup2(-C, -C),
     Tup2(C, -C),
 ];
-const TAU: f32 = 0.6;
-const TAU_INVERSE: f32 = 1. / TAU;
+pub const DEFAULT_TAU: f32 = 0.6;
 pub const OPPOSITE: [usize; 9] = [0, 3, 4, 1, 2, 7, 8, 5, 6];
 
+// Lallemand-Luo moment basis for the E_I ordering above, rows are
+// density, energy, energy squared, x momentum, x energy flux, y momentum, y energy flux,
+// and the two stress components pxx and pxy
+const MRT_M: [[f32; 9]; 9] = [
+    [1., 1., 1., 1., 1., 1., 1., 1., 1.],
+    [-4., -1., -1., -1., -1., 2., 2., 2., 2.],
+    [4., -2., -2., -2., -2., 1., 1., 1., 1.],
+    [0., 1., 0., -1., 0., 1., -1., -1., 1.],
+    [0., -2., 0., 2., 0., 1., -1., -1., 1.],
+    [0., 0., 1., 0., -1., 1., 1., -1., -1.],
+    [0., 0., -2., 0., 2., 1., 1., -1., -1.],
+    [0., 1., -1., 1., -1., 0., 0., 0., 0.],
+    [0., 0., 0., 0., 0., 1., -1., 1., -1.],
+];
+// the rows are orthogonal, so M^-1 = M^T / MRT_NORM
+const MRT_NORM: [f32; 9] = [9., 36., 36., 6., 12., 6., 12., 4., 4.];
+
+#[derive(Clone, Copy, Debug)]
+pub struct MrtRates {
+    pub bulk: f32,  // energy moment, sets the bulk viscosity
+    pub ghost: f32, // energy squared and energy flux moments, no hydrodynamic meaning
+}
+
+impl Default for MrtRates {
+    fn default() -> Self {
+        MrtRates {
+            bulk: 1.64,
+            ghost: 1.54,
+        }
+    }
+}
+
+// with this value of the TRT magic parameter the halfway bounce-back wall sits exactly
+// halfway between the fluid and obstacle cells, whatever the viscosity
+pub const TRT_MAGIC_WALL: f32 = 3. / 16.;
+
+// all viscosities are in lattice units (dx = dt = 1), nu = c_s^2 (tau - 1/2)
+pub fn tau_from_viscosity(viscosity: f32) -> f32 {
+    viscosity / C2 + 0.5
+}
+
+pub fn viscosity_from_tau(tau: f32) -> f32 {
+    C2 * (tau - 0.5)
+}
+
+pub fn viscosity_from_reynolds(reynolds: f32, velocity: f32, length: f32) -> f32 {
+    velocity * length / reynolds
+}
+
+// momentum given to a population of direction dir bouncing back from a wall moving with
+// wall_velocity, to be subtracted from the reflected population (Ladd)
+pub fn moving_wall_correction(dir: usize, density: f32, wall_velocity: Tup2<f32>) -> f32 {
+    2. * WEIGHTS[dir] * density * E_I[dir].dot(wall_velocity) / C2
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct FluidState {
     pub d_i: [f32; 9],
@@ -106,15 +160,28 @@ impl FluidState {
         self.d_i.iter().sum()
     }
 
-    pub fn velocity_sum(&self) -> Tup2<f32> {
-        let mut momentum = Tup2(0., 0.);
+    // with a body force the velocity is shifted by half the force (Guo scheme)
+    pub fn velocity_sum(&self, force: Tup2<f32>) -> Tup2<f32> {
+        let mut momentum = force * 0.5;
         for i in 0..9 {
             momentum = momentum + E_I[i] * self.d_i[i]
         }
         momentum / self.density
     }
 
-    pub fn collide(&mut self) {
+    // Guo forcing term, to be added after collision scaled by (1 - omega / 2)
+    pub fn get_force_source(velocity: Tup2<f32>, force: Tup2<f32>) -> [f32; 9] {
+        let mut source = [0.; 9];
+        for i in 0..9 {
+            source[i] = WEIGHTS[i]
+                * ((E_I[i] - velocity).dot(force) / C2
+                    + E_I[i].dot(velocity) * E_I[i].dot(force) / C4)
+        }
+        source
+    }
+
+    // omega = 1 / tau is the relaxation rate of the cell
+    pub fn collide(&mut self, omega: f32, force: Tup2<f32>) {
         self.d_i = sub_array(
             self.d_i,
             mul_array_k(
@@ -122,11 +189,69 @@ impl FluidState {
                     self.d_i,
                     Self::get_equilibrium_density(self.density, self.velocity),
                 ),
-                TAU_INVERSE,
+                omega,
             ),
         );
+        if force.mag_sq() > 0. {
+            let source = Self::get_force_source(self.velocity, force);
+            for i in 0..9 {
+                self.d_i[i] += (1. - 0.5 * omega) * source[i]
+            }
+        }
     }
-    
+
+    pub fn collide_mrt(&mut self, omega: f32, rates: MrtRates, force: Tup2<f32>) {
+        let rates = [0., rates.bulk, rates.ghost, 0., rates.ghost, 0., rates.ghost, omega, omega];
+        let non_eq = sub_array(
+            self.d_i,
+            Self::get_equilibrium_density(self.density, self.velocity),
+        );
+        // relax every moment towards the moments of the equilibrium, then map back
+        let mut relaxed_moments = [0.; 9];
+        for k in 0..9 {
+            let mut moment = 0.;
+            for i in 0..9 {
+                moment += MRT_M[k][i] * non_eq[i];
+            }
+            relaxed_moments[k] = rates[k] * moment / MRT_NORM[k];
+        }
+        // the forcing term is relaxed in moment space as well, every moment with its own rate
+        if force.mag_sq() > 0. {
+            let source = Self::get_force_source(self.velocity, force);
+            for k in 0..9 {
+                let mut moment = 0.;
+                for i in 0..9 {
+                    moment += MRT_M[k][i] * source[i];
+                }
+                relaxed_moments[k] -= (1. - 0.5 * rates[k]) * moment / MRT_NORM[k];
+            }
+        }
+        for i in 0..9 {
+            for k in 0..9 {
+                self.d_i[i] -= MRT_M[k][i] * relaxed_moments[k];
+            }
+        }
+    }
+
+    // the antisymmetric rate follows from magic = (1 / omega_plus - 1/2)(1 / omega_minus - 1/2)
+    pub fn collide_trt(&mut self, omega: f32, magic: f32, force: Tup2<f32>) {
+        let omega_minus = (magic / (omega.recip() - 0.5) + 0.5).recip();
+        let d_eq = Self::get_equilibrium_density(self.density, self.velocity);
+        let source = Self::get_force_source(self.velocity, force);
+        let mut d_i = self.d_i;
+        for i in 0..9 {
+            let opp = OPPOSITE[i];
+            let sym = 0.5 * (self.d_i[i] + self.d_i[opp] - d_eq[i] - d_eq[opp]);
+            let anti_sym = 0.5 * (self.d_i[i] - self.d_i[opp] - d_eq[i] + d_eq[opp]);
+            let source_sym = 0.5 * (source[i] + source[opp]);
+            let source_anti_sym = 0.5 * (source[i] - source[opp]);
+            d_i[i] += (1. - 0.5 * omega) * source_sym + (1. - 0.5 * omega_minus) * source_anti_sym
+                - omega * sym
+                - omega_minus * anti_sym;
+        }
+        self.d_i = d_i;
+    }
+
     pub fn advect(&mut self, neighbors_state: [Cell; 9]) {
         for i in 0..9 {
             match neighbors_state[i] {
@@ -138,7 +263,7 @@ impl FluidState {
     
     pub fn update_distribution(&mut self){
         self.density = self.density_sum();
-        self.velocity = self.velocity_sum()
+        self.velocity = self.velocity_sum(Tup2::default())
     }
     
 }
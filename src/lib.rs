@@ -0,0 +1,37 @@
+// the D2Q9 code loops over the direction index to read several per-direction tables at once
+#![allow(clippy::needless_range_loop)]
+
+pub mod boundary;
+pub mod checkpoint;
+pub mod collision;
+pub mod color;
+pub mod csv;
+pub mod expr;
+pub mod fields;
+pub mod fluid_struct;
+pub mod lattice;
+pub mod lattice_state;
+pub mod npy;
+pub mod obstacle_board;
+pub mod render;
+pub mod scenario;
+pub mod scenario_file;
+pub mod tup2;
+pub mod vec2;
+pub mod vtk;
+
+#[cfg(feature = "viewer")]
+pub mod canvas;
+#[cfg(feature = "viewer")]
+pub mod lattice_boltzmann_sim;
+#[cfg(feature = "viewer")]
+pub mod scene2d;
+
+pub use boundary::{BoundaryConfig, EdgeBoundary};
+pub use collision::{Bgk, CollisionModel, CollisionOperator, Mrt, Trt};
+pub use fluid_struct::{Kernel, LBFluidSim, Phase};
+pub use lattice::Lattice;
+pub use lattice_state::{Cell, FluidState};
+pub use obstacle_board::{GridStats, ObstacleBoard};
+pub use scenario_file::ScenarioFile;
+pub use tup2::Tup2;
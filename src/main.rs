@@ -1,14 +1,3 @@
-mod lattice_boltzmann_sim;
-mod canvas;
-mod scene2d;
-mod tup2;
-mod vec2;
-
-mod lattice_state;
-mod obstacle_board;
-
-mod fluid_struct;
-
 fn main() {
-    lattice_boltzmann_sim::main()
+    fluid::lattice_boltzmann_sim::main()
 }
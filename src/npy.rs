@@ -0,0 +1,180 @@
+use crate::fluid_struct::LBFluidSim;
+use crate::lattice_state::{Cell, FluidState};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+// NumPy arrays of the current state with the ndarray shapes of LBFluidSim, indexed [i, j] with
+// i along x: density, ux and uy of shape (nx, ny), d_i of shape (nx, ny, 9) and obstacle, a bool
+// mask of shape (nx, ny), the fields are NaN on obstacle cells, d_i is always after the collision,
+// each population in the slot of its own direction, whatever the kernel (see d_i_by_direction)
+//
+//     fields = numpy.load("fields_00001000.npz")
+//     density = numpy.ma.masked_invalid(fields["density"])
+
+// each array as the bytes of a .npy file
+pub fn snapshot_arrays(fluid_sim: &LBFluidSim) -> Vec<(&'static str, Vec<u8>)> {
+    let (nx, ny) = fluid_sim.shape;
+    let states = &fluid_sim.lattice;
+    let field = |value: fn(&FluidState) -> f32| {
+        let data: Vec<f32> = states
+            .cells()
+            .map(|cell| match cell {
+                Cell::Fluid(fs) => value(&fs),
+                Cell::Obstacle => f32::NAN,
+            })
+            .collect();
+        npy_f32(&[nx, ny], &data)
+    };
+    // the reference kernel keeps them streamed between updates, the AA kernel in swapped or
+    // pushed slots
+    let by_direction = fluid_sim.d_i_by_direction();
+    let d_i: Vec<f32> = (states.obstacle.indexed_iter())
+        .flat_map(|((i, j), obstacle)| match obstacle {
+            false => std::array::from_fn(|q| by_direction[[q, i, j]]),
+            true => [f32::NAN; 9],
+        })
+        .collect();
+    let obstacle: Vec<bool> = states.obstacle.iter().copied().collect();
+    vec![
+        ("density", field(|fs| fs.density)),
+        ("ux", field(|fs| fs.velocity.0)),
+        ("uy", field(|fs| fs.velocity.1)),
+        ("d_i", npy_f32(&[nx, ny, 9], &d_i)),
+        ("obstacle", npy_bool(&[nx, ny], &obstacle)),
+    ]
+}
+
+// one .npy file per array, named {stem}_{array}.npy
+pub fn write_npy(fluid_sim: &LBFluidSim, dir: &Path, stem: &str) -> std::io::Result<()> {
+    for (name, bytes) in snapshot_arrays(fluid_sim) {
+        std::fs::write(dir.join(format!("{}_{}.npy", stem, name)), bytes)?;
+    }
+    Ok(())
+}
+
+// all arrays in one uncompressed .npz archive, dated 1980-01-01 so equal states give equal files
+pub fn write_npz(fluid_sim: &LBFluidSim, path: &Path) -> std::io::Result<()> {
+    let mut archive = ZipWriter::new(File::create(path)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .last_modified_time(DateTime::default());
+    for (name, bytes) in snapshot_arrays(fluid_sim) {
+        archive.start_file(format!("{}.npy", name), options)?;
+        archive.write_all(&bytes)?;
+    }
+    archive.finish()?;
+    Ok(())
+}
+
+pub fn npy_f32(shape: &[usize], data: &[f32]) -> Vec<u8> {
+    let mut bytes = npy_header("<f4", shape);
+    for value in data {
+        bytes.extend(value.to_le_bytes());
+    }
+    bytes
+}
+
+pub fn npy_bool(shape: &[usize], data: &[bool]) -> Vec<u8> {
+    let mut bytes = npy_header("|b1", shape);
+    bytes.extend(data.iter().map(|value| *value as u8));
+    bytes
+}
+
+// format version 1.0, the header is padded with spaces so the data starts on a 64 byte boundary
+fn npy_header(descr: &str, shape: &[usize]) -> Vec<u8> {
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => {
+            let lengths: Vec<String> = shape.iter().map(|n| n.to_string()).collect();
+            format!("({})", lengths.join(", "))
+        }
+    };
+    let mut dict =
+        format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    let unpadded = 10 + dict.len() + 1;
+    dict.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    dict.push('\n');
+
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend((dict.len() as u16).to_le_bytes());
+    bytes.extend(dict.as_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fluid_struct::{Kernel, Phase};
+    use crate::scenario::build_scenario;
+
+    #[test]
+    fn snapshots_of_every_kernel_are_identical() {
+        let mut sims: Vec<_> = (Kernel::ALL.iter())
+            .map(|&kernel| {
+                let mut fluid_sim = build_scenario("cylinder", (40, 20), 0.05).unwrap();
+                fluid_sim.set_kernel(kernel);
+                fluid_sim
+            })
+            .collect();
+        let mut phases = Vec::new();
+        for _ in 0..6 {
+            for fluid_sim in &mut sims {
+                fluid_sim.update();
+                phases.push(fluid_sim.phase);
+            }
+            let reference = snapshot_arrays(&sims[0]);
+            for fluid_sim in &sims[1..] {
+                assert_eq!(snapshot_arrays(fluid_sim), reference, "{:?}", fluid_sim.kernel);
+            }
+        }
+        assert!([Phase::Streamed, Phase::Collided, Phase::Swapped, Phase::Pushed]
+            .iter()
+            .all(|phase| phases.contains(phase)));
+    }
+
+    // every array is stored as it is, and the same state gives the same file
+    #[test]
+    fn npz_round_trip() {
+        let fluid_sim = build_scenario("cylinder", (40, 20), 0.05).unwrap();
+        let name = format!("fluid_{}_round_trip.npz", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        write_npz(&fluid_sim, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        write_npz(&fluid_sim, &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        std::fs::remove_file(&path).unwrap();
+
+        let entries = snapshot_arrays(&fluid_sim);
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), entries.len());
+        for (name, data) in &entries {
+            let mut file = archive.by_name(&format!("{}.npy", name)).unwrap();
+            assert_eq!(file.compression(), CompressionMethod::Stored);
+            let mut read = Vec::new();
+            std::io::Read::read_to_end(&mut file, &mut read).unwrap();
+            assert_eq!(&read, data, "{}", name);
+        }
+    }
+
+    #[test]
+    fn npy_header_layout() {
+        let bytes = npy_f32(&[3, 2], &[0., 1., 2., 3., 4., 5.]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 2), }"));
+        assert!(header.ends_with(" \n"));
+        assert_eq!(bytes.len(), 10 + header_len + 6 * 4);
+        assert_eq!(&bytes[bytes.len() - 4..], &5f32.to_le_bytes());
+
+        let bytes = npy_bool(&[4], &[true, false, true, true]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'descr': '|b1'") && header.contains("'shape': (4,)"));
+        assert_eq!(&bytes[10 + header_len..], &[1, 0, 1, 1]);
+    }
+}
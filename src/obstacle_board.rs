@@ -1,7 +1,18 @@
 use bit_vec::BitVec;
 use ndarray::Array2;
+use std::collections::HashMap;
+use crate::lattice_state::E_I;
 use crate::tup2::Tup2;
 
+// label of the cells that are not part of an obstacle
+pub const NO_OBSTACLE: u32 = u32::MAX;
+
+// index of the obstacle a label stands for
+#[inline]
+pub fn obstacle_index(label: u32) -> Option<usize> {
+    (label != NO_OBSTACLE).then_some(label as usize)
+}
+
 #[derive(Clone, Debug)]
 pub struct GridStats {
     pub shape: (usize, usize),
@@ -42,10 +53,13 @@ impl GridStats {
         grid_pos
     }
 
-    pub fn compute_scalar_field(&self, function: fn(Tup2<f32>) -> f32) -> Array2<f32> {
+    pub fn compute_scalar_field(&self, function: impl Fn(Tup2<f32>) -> f32) -> Array2<f32> {
         Array2::from_shape_fn(self.shape, |(i, j)| function(self.grid_pos[[i, j]]))
     }
-    pub fn compute_vector_field(&self, function: fn(Tup2<f32>) -> Tup2<f32>) -> Array2<Tup2<f32>> {
+    pub fn compute_vector_field(
+        &self,
+        function: impl Fn(Tup2<f32>) -> Tup2<f32>,
+    ) -> Array2<Tup2<f32>> {
         Array2::from_shape_fn(self.shape, |(i, j)| function(self.grid_pos[[i, j]]))
     }
 }
@@ -53,6 +67,10 @@ impl GridStats {
 pub struct ObstacleBoard {
     pub grid_stats: GridStats,
     pub bit_vec: BitVec,
+    pub wall_velocity: HashMap<usize, Tup2<f32>>, // only for moving obstacle cells, by linear index
+    // fraction of the link from a cell along a direction at which a drawn curve cuts it,
+    // by (linear index, direction)
+    pub link_fraction: HashMap<(usize, usize), f32>,
 }
 
 impl ObstacleBoard {
@@ -61,11 +79,12 @@ impl ObstacleBoard {
         ObstacleBoard {
             grid_stats,
             bit_vec,
+            wall_velocity: HashMap::new(),
+            link_fraction: HashMap::new(),
         }
     }
     pub fn get_index(&self, i: usize, j: usize) -> usize {
-        let index = i + j * self.grid_stats.shape.0;
-        index
+        i + j * self.grid_stats.shape.0
     }
 
     pub fn set_obstacle(&mut self, i: usize, j: usize) {
@@ -73,6 +92,16 @@ impl ObstacleBoard {
         self.bit_vec.set(index, true)
     }
 
+    pub fn set_wall_velocity(&mut self, i: usize, j: usize, velocity: Tup2<f32>) {
+        let index = self.get_index(i, j);
+        self.wall_velocity.insert(index, velocity);
+    }
+
+    pub fn get_wall_velocity(&self, i: usize, j: usize) -> Tup2<f32> {
+        let index = self.get_index(i, j);
+        self.wall_velocity.get(&index).copied().unwrap_or_default()
+    }
+
     pub fn new(grid_stats: GridStats, filled_index: Vec<(usize, usize)>) -> Self {
         let mut obstacle_grid = ObstacleBoard::new_empty(grid_stats);
         for indices in filled_index {
@@ -86,9 +115,58 @@ impl ObstacleBoard {
         self.bit_vec.get(index).unwrap()
     }
 
+    // groups the obstacle cells into connected obstacles, diagonal neighbors included since drawn
+    // lines step diagonally, and returns the label of every cell with the number of obstacles
+    pub fn label_obstacles(&self) -> (Array2<u32>, usize) {
+        let shape = self.grid_stats.shape;
+        let mut labels = Array2::from_elem(shape, NO_OBSTACLE);
+        let mut count = 0;
+        for i in 0..shape.0 {
+            for j in 0..shape.1 {
+                if !self.exist_obstacle(i, j) || labels[[i, j]] != NO_OBSTACLE {
+                    continue;
+                }
+                labels[[i, j]] = count as u32;
+                let mut stack = vec![(i, j)];
+                while let Some((ci, cj)) = stack.pop() {
+                    for dir in 1..9 {
+                        let ni = ci as isize + E_I[dir].0 as isize;
+                        let nj = cj as isize + E_I[dir].1 as isize;
+                        if ni < 0 || nj < 0 || ni >= shape.0 as isize || nj >= shape.1 as isize {
+                            continue;
+                        }
+                        let (ni, nj) = (ni as usize, nj as usize);
+                        if self.exist_obstacle(ni, nj) && labels[[ni, nj]] == NO_OBSTACLE {
+                            labels[[ni, nj]] = count as u32;
+                            stack.push((ni, nj));
+                        }
+                    }
+                }
+                count += 1;
+            }
+        }
+        (labels, count)
+    }
+
+    // width and height in cells of each labelled obstacle
+    pub fn obstacle_sizes(labels: &Array2<u32>, count: usize) -> Vec<Tup2<f32>> {
+        let mut min = vec![Tup2(usize::MAX, usize::MAX); count];
+        let mut max = vec![Tup2(0, 0); count];
+        for ((i, j), label) in labels.indexed_iter() {
+            if let Some(label) = obstacle_index(*label) {
+                min[label] = Tup2(min[label].0.min(i), min[label].1.min(j));
+                max[label] = Tup2(max[label].0.max(i), max[label].1.max(j));
+            }
+        }
+        min.into_iter()
+            .zip(max)
+            .map(|(min, max)| (max - min).to_f32() + Tup2(1., 1.))
+            .collect()
+    }
+
+    // nearest cell, so the cells of a drawn curve lie within half a cell of it
     pub fn pos_to_grid_index(&self, pos: Tup2<f32>) -> Tup2<usize> {
-        let index = ((pos * self.grid_stats.spacing_inverse) + self.grid_stats.mid_point).floor();
-        index
+        ((pos * self.grid_stats.spacing_inverse) + self.grid_stats.mid_point).round()
     }
     pub fn pos_to_linear_index(&self, pos: Tup2<f32>) -> usize {
         let grid_index = self.pos_to_grid_index(pos);
@@ -97,9 +175,14 @@ impl ObstacleBoard {
         index
     }
 
-    pub fn set_line(&mut self, p1: Tup2<f32>, p2: Tup2<f32>) {
+    pub fn line_cells(&self, p1: Tup2<f32>, p2: Tup2<f32>) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
         let grid_i1 = self.pos_to_grid_index(p1);
         let grid_i2 = self.pos_to_grid_index(p2);
+        // both ends in the same cell, the gradient below would be 0 / 0
+        if grid_i1.0 == grid_i2.0 && grid_i1.1 == grid_i2.1 {
+            return vec![(grid_i1.0, grid_i1.1)];
+        }
         // println!("grid_i1: {:?}", grid_i1.1);
         // println!("grid_i2: {:?}", grid_i2.1);
         // print!("y displacement: {}", grid_i2.1 - grid_i1.1);
@@ -113,7 +196,7 @@ impl ObstacleBoard {
             for x in ix_min..ix_max + 1 {
                 let y = ((gradient * (x as f32 - grid_i1.0 as f32)) + grid_i1.1 as f32).round()
                     as usize;
-                self.set_obstacle(x, y)
+                cells.push((x, y))
             }
         } else {
             let gradient = (grid_i2.0 as isize- grid_i1.0 as isize) as f32 / (grid_i2.1 as isize - grid_i1.1 as isize) as f32;
@@ -121,13 +204,100 @@ impl ObstacleBoard {
             for y in iy_min..iy_max + 1 {
                 let x = ((gradient * (y as f32 - grid_i1.1 as f32)) + grid_i1.0 as f32).round()
                     as usize;
-                self.set_obstacle(x, y)
+                cells.push((x, y))
+            }
+        }
+        cells
+    }
+
+    // stores where the segment cuts the links between nearby cells, so the wall can be placed
+    // at its real position rather than halfway along the link. The rasterized cells can sit up to
+    // half a cell in front of the curve, so the cut is also searched past the end of the link,
+    // a fraction above 1 then puts the wall on the obstacle cell
+    pub fn set_link_fractions(&mut self, p1: Tup2<f32>, p2: Tup2<f32>) {
+        let shape = self.grid_stats.shape;
+        let grid_i1 = (p1 * self.grid_stats.spacing_inverse) + self.grid_stats.mid_point;
+        let grid_i2 = (p2 * self.grid_stats.spacing_inverse) + self.grid_stats.mid_point;
+        let (nx, ny) = (shape.0 as isize, shape.1 as isize);
+        let i_min = (grid_i1.0.min(grid_i2.0).floor() as isize - 2).max(0) as usize;
+        let i_max = (grid_i1.0.max(grid_i2.0).ceil() as isize + 2).min(nx - 1) as usize;
+        let j_min = (grid_i1.1.min(grid_i2.1).floor() as isize - 2).max(0) as usize;
+        let j_max = (grid_i1.1.max(grid_i2.1).ceil() as isize + 2).min(ny - 1) as usize;
+
+        let segment = p2 - p1;
+        for i in i_min..i_max + 1 {
+            for j in j_min..j_max + 1 {
+                let start = self.grid_stats.grid_pos[[i, j]];
+                for dir in 1..9 {
+                    let link = E_I[dir] * self.grid_stats.spacing;
+                    // solve start + t link = p1 + s segment
+                    let cross = link.0 * segment.1 - link.1 * segment.0;
+                    if cross == 0. {
+                        continue;
+                    }
+                    let offset = p1 - start;
+                    let t = (offset.0 * segment.1 - offset.1 * segment.0) / cross;
+                    let s = (offset.0 * link.1 - offset.1 * link.0) / cross;
+                    if !(0. ..=1.5).contains(&t) || !(0. ..=1.).contains(&s) {
+                        continue;
+                    }
+                    let index = self.get_index(i, j);
+                    let fraction = self.link_fraction.entry((index, dir)).or_insert(t);
+                    *fraction = fraction.min(t);
+                }
             }
         }
     }
+
+    pub fn get_link_fraction(&self, i: usize, j: usize, dir: usize) -> Option<f32> {
+        let fraction = self.link_fraction.get(&(self.get_index(i, j), dir))?;
+        Some(fraction.min(1.))
+    }
+
+    pub fn set_line(&mut self, p1: Tup2<f32>, p2: Tup2<f32>) {
+        for (i, j) in self.line_cells(p1, p2) {
+            self.set_obstacle(i, j)
+        }
+        self.set_link_fractions(p1, p2)
+    }
+
+    // a wall sliding along itself with the given velocity, like a conveyor belt or a cavity lid
+    pub fn set_moving_line(&mut self, p1: Tup2<f32>, p2: Tup2<f32>, velocity: Tup2<f32>) {
+        for (i, j) in self.line_cells(p1, p2) {
+            self.set_obstacle(i, j);
+            self.set_wall_velocity(i, j, velocity)
+        }
+        self.set_link_fractions(p1, p2)
+    }
+
     pub fn set_piecewise_curve(&mut self, vertices: Vec<Tup2<f32>>) {
         for i in 0..vertices.len() - 1 {
             self.set_line(vertices[i], vertices[i + 1])
         }
     }
+
+    pub fn set_moving_piecewise_curve(&mut self, vertices: Vec<Tup2<f32>>, velocity: Tup2<f32>) {
+        for i in 0..vertices.len() - 1 {
+            self.set_moving_line(vertices[i], vertices[i + 1], velocity)
+        }
+    }
+
+    // rigid rotation around center, angular velocity counterclockwise in radians per time step
+    pub fn set_rotating_piecewise_curve(
+        &mut self,
+        vertices: Vec<Tup2<f32>>,
+        center: Tup2<f32>,
+        angular_velocity: f32,
+    ) {
+        for k in 0..vertices.len() - 1 {
+            for (i, j) in self.line_cells(vertices[k], vertices[k + 1]) {
+                // wall velocity in lattice units, so the radius is measured in cells
+                let r = (self.grid_stats.grid_pos[[i, j]] - center)
+                    * self.grid_stats.spacing_inverse;
+                self.set_obstacle(i, j);
+                self.set_wall_velocity(i, j, Tup2(-r.1, r.0) * angular_velocity)
+            }
+            self.set_link_fractions(vertices[k], vertices[k + 1])
+        }
+    }
 }
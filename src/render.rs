@@ -0,0 +1,168 @@
+use crate::color::{density_color, ARROW_COLOR, OBSTACLE_COLOR};
+use crate::fluid_struct::LBFluidSim;
+use crate::lattice_state::Cell;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// software version of the viewer picture for runs without a display: density colored cells,
+// red obstacles and white velocity direction lines, y pointing up
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    pub scale: usize, // pixels per cell along each axis
+    pub max_density: f32,
+    pub arrow_every: usize, // cells between two velocity lines, 0 draws none
+    pub arrow_length: f32,  // in cells
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            scale: 4,
+            max_density: 1.,
+            arrow_every: 4,
+            arrow_length: 2.,
+        }
+    }
+}
+
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>, // rows from the top
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut rgba = vec![0; 4 * width * height];
+        for pixel in rgba.chunks_mut(4) {
+            pixel[3] = 255;
+        }
+        Frame {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: i64, y: i64, color: (u8, u8, u8)) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let index = 4 * (y as usize * self.width + x as usize);
+        self.rgba[index..index + 3].copy_from_slice(&[color.0, color.1, color.2]);
+    }
+
+    pub fn fill_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: (u8, u8, u8),
+    ) {
+        for py in y..(y + height).min(self.height) {
+            for px in x..(x + width).min(self.width) {
+                self.set_pixel(px as i64, py as i64, color);
+            }
+        }
+    }
+
+    // Bresenham, in pixel coordinates
+    pub fn draw_line(&mut self, from: (f32, f32), to: (f32, f32), color: (u8, u8, u8)) {
+        let (mut x, mut y) = (from.0.round() as i64, from.1.round() as i64);
+        let (x_end, y_end) = (to.0.round() as i64, to.1.round() as i64);
+        let (dx, dy) = ((x_end - x).abs(), -(y_end - y).abs());
+        let (step_x, step_y) = ((x_end - x).signum(), (y_end - y).signum());
+        let mut error = dx + dy;
+        loop {
+            self.set_pixel(x, y, color);
+            if x == x_end && y == y_end {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    // 8 bit RGBA
+    pub fn write_png(&self, path: &Path) -> std::io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgba)?;
+        Ok(writer.finish()?)
+    }
+}
+
+pub fn render_frame(fluid_sim: &LBFluidSim, options: &RenderOptions) -> Frame {
+    let (nx, ny) = fluid_sim.shape;
+    let scale = options.scale.max(1);
+    let mut frame = Frame::new(nx * scale, ny * scale);
+    // center of cell (i, j) in pixels
+    let pixel = |i: usize, j: usize| {
+        let half = scale as f32 / 2.;
+        (i as f32 * scale as f32 + half, (ny - 1 - j) as f32 * scale as f32 + half)
+    };
+
+    for i in 0..nx {
+        for j in 0..ny {
+            let color = match fluid_sim.lattice.get(i, j) {
+                Cell::Fluid(fs) => density_color(fs.density, options.max_density),
+                Cell::Obstacle => OBSTACLE_COLOR,
+            };
+            frame.fill_rect(i * scale, (ny - 1 - j) * scale, scale, scale, color);
+        }
+    }
+
+    if options.arrow_every > 0 {
+        let length = options.arrow_length * scale as f32;
+        for i in (0..nx).step_by(options.arrow_every) {
+            for j in (0..ny).step_by(options.arrow_every) {
+                let Cell::Fluid(fluid_state) = fluid_sim.lattice.get(i, j) else {
+                    continue;
+                };
+                if fluid_state.velocity.mag_sq() == 0. {
+                    continue;
+                }
+                let direction = fluid_state.velocity.normalize() * length;
+                let from = pixel(i, j);
+                let to = (from.0 + direction.0, from.1 - direction.1);
+                frame.draw_line(from, to, ARROW_COLOR);
+            }
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::build_scenario;
+
+    #[test]
+    fn png_frame_round_trip() {
+        let fluid_sim = build_scenario("cylinder", (30, 20), 0.05).unwrap();
+        let options = RenderOptions { scale: 3, ..RenderOptions::default() };
+        let frame = render_frame(&fluid_sim, &options);
+        let path = std::env::temp_dir().join(format!("fluid_{}_frame.png", std::process::id()));
+        frame.write_png(&path).unwrap();
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((info.width, info.height), (90, 60));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(&decoded[..info.buffer_size()], &frame.rgba[..]);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::boundary::{BoundaryConfig, EdgeBoundary};
+use crate::fluid_struct::LBFluidSim;
+use crate::obstacle_board::{GridStats, ObstacleBoard};
+use crate::tup2::Tup2;
+use std::f32::consts::PI;
+
+// built-in setups that can be run without a window, all of them scale with the grid shape
+pub const SCENARIOS: [&str; 4] = ["plate", "cylinder", "channel", "cavity"];
+
+const INLET_VELOCITY: f32 = 0.1;
+
+pub fn build_scenario(name: &str, shape: (usize, usize), viscosity: f32) -> Result<LBFluidSim, String> {
+    let grid_stats = GridStats::new(shape, 1.);
+    let fluid_sim = match name {
+        "plate" => plate(grid_stats, viscosity),
+        "cylinder" => cylinder(grid_stats, viscosity),
+        "channel" => channel(grid_stats, viscosity),
+        "cavity" => cavity(grid_stats, viscosity),
+        _ => {
+            return Err(format!(
+                "unknown scenario '{}', expected one of {}",
+                name,
+                SCENARIOS.join(", ")
+            ))
+        }
+    };
+    Ok(fluid_sim)
+}
+
+fn unit_density(_pos: Tup2<f32>) -> f32 {
+    1.
+}
+
+fn at_rest(_pos: Tup2<f32>) -> Tup2<f32> {
+    Tup2(0., 0.)
+}
+
+fn inlet_flow(_pos: Tup2<f32>) -> Tup2<f32> {
+    Tup2(INLET_VELOCITY, 0.)
+}
+
+// half of the width and height of the domain
+fn half_extent(grid_stats: &GridStats) -> Tup2<f32> {
+    grid_stats.mid_point * grid_stats.spacing
+}
+
+// the demo of the viewer, a flat plate across an inflow
+fn plate(grid_stats: GridStats, viscosity: f32) -> LBFluidSim {
+    let half = half_extent(&grid_stats).1 * 0.4;
+    let mut obstacle_board = ObstacleBoard::new_empty(grid_stats);
+    obstacle_board.set_piecewise_curve(vec![Tup2(0., half), Tup2(0., -half)]);
+    let boundaries =
+        BoundaryConfig::inlet_outlet(Tup2(INLET_VELOCITY, 0.), 1., EdgeBoundary::Periodic);
+    LBFluidSim::new(obstacle_board, unit_density, inlet_flow, viscosity, boundaries)
+}
+
+// Karman vortex street behind a cylinder between free-slip walls
+fn cylinder(grid_stats: GridStats, viscosity: f32) -> LBFluidSim {
+    let half = half_extent(&grid_stats);
+    let center = Tup2(-half.0 * 0.5, 0.);
+    let radius = half.1 * 0.2;
+    let vertices = (0..=64)
+        .map(|k| {
+            let angle = 2. * PI * k as f32 / 64.;
+            center + Tup2(angle.cos(), angle.sin()) * radius
+        })
+        .collect();
+    let mut obstacle_board = ObstacleBoard::new_empty(grid_stats);
+    obstacle_board.set_piecewise_curve(vertices);
+    let boundaries =
+        BoundaryConfig::inlet_outlet(Tup2(INLET_VELOCITY, 0.), 1., EdgeBoundary::FreeSlip);
+    LBFluidSim::new(obstacle_board, unit_density, inlet_flow, viscosity, boundaries)
+}
+
+// Poiseuille flow driven by a uniform body force between no-slip walls
+fn channel(grid_stats: GridStats, viscosity: f32) -> LBFluidSim {
+    let obstacle_board = ObstacleBoard::new_empty(grid_stats);
+    let boundaries = BoundaryConfig::new(
+        EdgeBoundary::Periodic,
+        EdgeBoundary::Periodic,
+        EdgeBoundary::Wall,
+        EdgeBoundary::Wall,
+    );
+    let mut fluid_sim =
+        LBFluidSim::new(obstacle_board, unit_density, at_rest, viscosity, boundaries);
+    fluid_sim.set_uniform_force(Tup2(1e-6, 0.));
+    fluid_sim
+}
+
+// lid-driven cavity, the top edge slides to the right
+fn cavity(grid_stats: GridStats, viscosity: f32) -> LBFluidSim {
+    let obstacle_board = ObstacleBoard::new_empty(grid_stats);
+    let boundaries = BoundaryConfig::new(
+        EdgeBoundary::Wall,
+        EdgeBoundary::Wall,
+        EdgeBoundary::Wall,
+        EdgeBoundary::MovingWall(Tup2(INLET_VELOCITY, 0.)),
+    );
+    LBFluidSim::new(obstacle_board, unit_density, at_rest, viscosity, boundaries)
+}
@@ -0,0 +1,316 @@
+use crate::boundary::{BoundaryConfig, EdgeBoundary};
+use crate::collision::{Bgk, Mrt, Trt};
+use crate::expr::Expr;
+use crate::fluid_struct::LBFluidSim;
+use crate::obstacle_board::{GridStats, ObstacleBoard};
+use crate::tup2::Tup2;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// a whole setup in one TOML file, see scenarios/ for examples
+//
+//     steps = 20000
+//     viscosity = 0.02
+//
+//     [grid]
+//     shape = [400, 100]
+//     length = 1.0
+//
+//     [initial]
+//     density = "1"
+//     velocity = ["0.1", "0"]
+//
+//     [boundaries]
+//     left = { type = "velocity", velocity = [0.1, 0.0] }
+//     right = { type = "density", density = 1.0 }
+//
+//     [[obstacles]]
+//     points = [[0.0, 0.1], [0.0, -0.1]]
+//
+// positions are in the units of grid.length with the origin in the middle of the domain,
+// velocities and viscosity are in lattice units
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioFile {
+    pub steps: usize,
+    pub viscosity: f32,
+    pub grid: GridSpec,
+    #[serde(default)]
+    pub initial: InitialSpec,
+    #[serde(default)]
+    pub boundaries: BoundarySpec,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleSpec>,
+    #[serde(default)]
+    pub collision: CollisionSpec,
+    pub smagorinsky: Option<f32>,
+    pub force: Option<[String; 2]>, // body force expressions
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GridSpec {
+    pub shape: (usize, usize),
+    #[serde(default = "unit_length")]
+    pub length: f32,
+}
+
+fn unit_length() -> f32 {
+    1.
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InitialSpec {
+    pub density: String,
+    pub velocity: [String; 2],
+}
+
+impl Default for InitialSpec {
+    fn default() -> Self {
+        InitialSpec {
+            density: "1".to_string(),
+            velocity: ["0".to_string(), "0".to_string()],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoundarySpec {
+    #[serde(default)]
+    pub left: EdgeSpec,
+    #[serde(default)]
+    pub right: EdgeSpec,
+    #[serde(default)]
+    pub bottom: EdgeSpec,
+    #[serde(default)]
+    pub top: EdgeSpec,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum EdgeSpec {
+    #[default]
+    Periodic,
+    Wall,
+    MovingWall { velocity: [f32; 2] },
+    FreeSlip,
+    Open,
+    Velocity { velocity: [f32; 2] },
+    Density { density: f32 },
+}
+
+impl EdgeSpec {
+    pub fn to_edge_boundary(self) -> EdgeBoundary {
+        match self {
+            EdgeSpec::Periodic => EdgeBoundary::Periodic,
+            EdgeSpec::Wall => EdgeBoundary::Wall,
+            EdgeSpec::MovingWall { velocity } => {
+                EdgeBoundary::MovingWall(Tup2(velocity[0], velocity[1]))
+            }
+            EdgeSpec::FreeSlip => EdgeBoundary::FreeSlip,
+            EdgeSpec::Open => EdgeBoundary::Open,
+            EdgeSpec::Velocity { velocity } => {
+                EdgeBoundary::Velocity(Tup2(velocity[0], velocity[1]))
+            }
+            EdgeSpec::Density { density } => EdgeBoundary::Density(density),
+        }
+    }
+}
+
+// a polyline, closed by repeating the first point at the end
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObstacleSpec {
+    pub points: Vec<[f32; 2]>,
+    pub velocity: Option<[f32; 2]>, // wall velocity of a sliding obstacle
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionSpec {
+    #[default]
+    Bgk,
+    Mrt,
+    Trt,
+}
+
+impl ScenarioFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let scenario: ScenarioFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let (nx, ny) = self.grid.shape;
+        if nx < 2 || ny < 2 {
+            return Err(format!("grid shape must be at least 2x2, got {}x{}", nx, ny));
+        }
+        if self.grid.length <= 0. {
+            return Err(format!("grid length must be positive, got {}", self.grid.length));
+        }
+        if self.viscosity <= 0. {
+            return Err(format!("viscosity must be positive, got {}", self.viscosity));
+        }
+        let periodic = |edge: EdgeSpec| edge.to_edge_boundary().is_periodic();
+        let b = &self.boundaries;
+        if periodic(b.left) != periodic(b.right) || periodic(b.bottom) != periodic(b.top) {
+            return Err("opposite edges must both be periodic or both not".to_string());
+        }
+        // parsed again in build, checked here so a typo is reported with the file name
+        let mut expressions = vec![&self.initial.density];
+        expressions.extend(&self.initial.velocity);
+        expressions.extend(self.force.iter().flatten());
+        for expression in expressions {
+            Expr::parse(expression)?;
+        }
+        if let Some(obstacle) = self.obstacles.iter().find(|o| o.points.len() < 2) {
+            return Err(format!("an obstacle needs at least 2 points, got {:?}", obstacle.points));
+        }
+        // the cell a point is drawn in, rounded like ObstacleBoard::pos_to_grid_index
+        let grid_stats = GridStats::new(self.grid.shape, self.grid.length);
+        for (k, obstacle) in self.obstacles.iter().enumerate() {
+            for point in &obstacle.points {
+                let index = Tup2(point[0], point[1]) * grid_stats.spacing_inverse
+                    + grid_stats.mid_point;
+                let (i, j) = (index.0.round(), index.1.round());
+                if !((0. ..nx as f32).contains(&i) && (0. ..ny as f32).contains(&j)) {
+                    return Err(format!(
+                        "point {:?} of obstacle {} is outside the {}x{} grid",
+                        point,
+                        k + 1,
+                        nx,
+                        ny
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn boundary_config(&self) -> BoundaryConfig {
+        let b = &self.boundaries;
+        BoundaryConfig::new(
+            b.left.to_edge_boundary(),
+            b.right.to_edge_boundary(),
+            b.bottom.to_edge_boundary(),
+            b.top.to_edge_boundary(),
+        )
+    }
+
+    pub fn build(&self) -> Result<LBFluidSim, String> {
+        // again, the shape or viscosity may have been changed since parse
+        self.validate()?;
+        let density = Expr::parse(&self.initial.density)?;
+        let velocity = [
+            Expr::parse(&self.initial.velocity[0])?,
+            Expr::parse(&self.initial.velocity[1])?,
+        ];
+
+        let grid_stats = GridStats::new(self.grid.shape, self.grid.length);
+        let mut obstacle_board = ObstacleBoard::new_empty(grid_stats);
+        for obstacle in &self.obstacles {
+            let vertices = obstacle.points.iter().map(|p| Tup2(p[0], p[1])).collect();
+            match obstacle.velocity {
+                Some(v) => obstacle_board.set_moving_piecewise_curve(vertices, Tup2(v[0], v[1])),
+                None => obstacle_board.set_piecewise_curve(vertices),
+            }
+        }
+
+        let mut fluid_sim = LBFluidSim::new(
+            obstacle_board,
+            |pos| density.eval(pos),
+            |pos| Tup2(velocity[0].eval(pos), velocity[1].eval(pos)),
+            self.viscosity,
+            self.boundary_config(),
+        );
+        match self.collision {
+            CollisionSpec::Bgk => fluid_sim.set_collision(Bgk),
+            CollisionSpec::Mrt => fluid_sim.set_collision(Mrt::default()),
+            CollisionSpec::Trt => fluid_sim.set_collision(Trt::default()),
+        }
+        if let Some(constant) = self.smagorinsky {
+            fluid_sim.set_smagorinsky(constant);
+        }
+        if let Some([fx, fy]) = &self.force {
+            let (fx, fy) = (Expr::parse(fx)?, Expr::parse(fy)?);
+            fluid_sim.set_force_field(|pos| Tup2(fx.eval(pos), fy.eval(pos)));
+        }
+        Ok(fluid_sim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the grid is 2 long and 1 high, so x runs over -1..1 and y over -0.5..0.5
+    fn with_obstacle(points: &str) -> Result<ScenarioFile, String> {
+        let text = format!(
+            "steps = 1\nviscosity = 0.1\n[grid]\nshape = [41, 21]\nlength = 2.0\n\
+             [[obstacles]]\npoints = [[0.0, 0.0], [0.0, 0.1]]\n\
+             [[obstacles]]\npoints = {}\n",
+            points
+        );
+        ScenarioFile::parse(&text)
+    }
+
+    #[test]
+    fn shipped_scenarios_load() {
+        for entry in std::fs::read_dir("scenarios").unwrap() {
+            let path = entry.unwrap().path();
+            ScenarioFile::load(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn saved_scenarios_read_back_the_same() {
+        for entry in std::fs::read_dir("scenarios").unwrap() {
+            let scenario = ScenarioFile::load(&entry.unwrap().path()).unwrap();
+            let text = toml::to_string(&scenario).unwrap();
+            let read = ScenarioFile::parse(&text).unwrap();
+            assert_eq!(format!("{:?}", read), format!("{:?}", scenario), "{}", text);
+        }
+    }
+
+    #[test]
+    fn obstacles_inside_the_grid() {
+        assert!(with_obstacle("[[-1.0, -0.5], [1.0, 0.5]]").is_ok());
+        let scenario = with_obstacle("[[-1.0, 0.5], [1.0, -0.5]]").unwrap();
+        assert!(scenario.build().is_ok());
+    }
+
+    #[test]
+    fn obstacles_outside_the_grid() {
+        for points in [
+            "[[0.0, 0.0], [0.0, 0.6]]",
+            "[[0.0, -0.6], [0.0, 0.0]]",
+            "[[1.1, 0.0], [0.0, 0.0]]",
+            "[[0.0, 0.0], [-1.1, 0.0]]",
+            "[[0.0, 0.0], [nan, 0.0]]",
+        ] {
+            let error = with_obstacle(points).unwrap_err();
+            assert!(error.contains("of obstacle 2 is outside the 41x21 grid"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn build_checks_a_changed_shape() {
+        let mut scenario = with_obstacle("[[0.0, -0.5], [0.0, 0.5]]").unwrap();
+        scenario.grid.shape = (41, 11);
+        assert!(scenario.build().is_err());
+    }
+}
@@ -1,6 +1,5 @@
 use crate::vec2::Vec2;
 use std::ops::{Add, Div, Mul, Neg, Sub};
-use palette::num::Sqrt;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Tup2<T>(pub T, pub T);
@@ -41,6 +40,9 @@ impl Tup2<f32> {
     pub fn floor(self) -> Tup2<usize> {
         Tup2((self.0).floor() as usize, (self.1).floor() as usize)
     }
+    pub fn round(self) -> Tup2<usize> {
+        Tup2((self.0).round() as usize, (self.1).round() as usize)
+    }
 }
 
 impl Tup2<usize> {
@@ -1,7 +1,6 @@
 use std::f32::consts::PI;
 use std::ops::{Add, Div, Mul, Neg, Sub};
-use std::process::Output;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vec2<T>{
@@ -34,9 +33,9 @@ impl Vec2<f32>{
     }
     
     pub fn random(mag_range: (f32, f32)) -> Self{
-        let mut rng = thread_rng();
-        let mag = rng.gen_range(mag_range.0..mag_range.1);
-        let angle = rng.gen_range(0f32..2. * PI);
+        let mut rng = rand::rng();
+        let mag = rng.random_range(mag_range.0..mag_range.1);
+        let angle = rng.random_range(0f32..2. * PI);
         Vec2::new(angle.cos(), angle.sin()) * mag
     }
 }
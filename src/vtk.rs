@@ -0,0 +1,135 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crate::fields::{density_field, obstacle_mask, velocity_field, vorticity};
+use crate::fluid_struct::LBFluidSim;
+use crate::obstacle_board::GridStats;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// the lattice as VTK image data for ParaView, one point per node with density, velocity,
+// vorticity and the obstacle mask, points in the units of GridStats::length
+// x runs fastest, as VTK expects
+
+fn origin(grid_stats: &GridStats) -> (f32, f32) {
+    let origin = grid_stats.mid_point * -grid_stats.spacing;
+    (origin.0, origin.1)
+}
+
+// legacy ASCII .vtk
+pub fn write_vtk(fluid_sim: &LBFluidSim, path: &Path) -> std::io::Result<()> {
+    let grid_stats = &fluid_sim.obstacle_board.grid_stats;
+    let (nx, ny) = fluid_sim.shape;
+    let (ox, oy) = origin(grid_stats);
+    let spacing = grid_stats.spacing;
+    let density = density_field(fluid_sim);
+    let velocity = velocity_field(fluid_sim);
+    let vorticity = vorticity(fluid_sim);
+    let obstacle = obstacle_mask(fluid_sim);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "# vtk DataFile Version 3.0")?;
+    writeln!(writer, "lattice Boltzmann step {}", fluid_sim.step)?;
+    writeln!(writer, "ASCII")?;
+    writeln!(writer, "DATASET STRUCTURED_POINTS")?;
+    writeln!(writer, "DIMENSIONS {} {} 1", nx, ny)?;
+    writeln!(writer, "ORIGIN {} {} 0", ox, oy)?;
+    writeln!(writer, "SPACING {} {} {}", spacing, spacing, spacing)?;
+    writeln!(writer, "POINT_DATA {}", nx * ny)?;
+
+    writeln!(writer, "SCALARS density float 1\nLOOKUP_TABLE default")?;
+    for j in 0..ny {
+        for i in 0..nx {
+            writeln!(writer, "{}", density[[i, j]])?;
+        }
+    }
+    writeln!(writer, "VECTORS velocity float")?;
+    for j in 0..ny {
+        for i in 0..nx {
+            writeln!(writer, "{} {} 0", velocity[[i, j]].0, velocity[[i, j]].1)?;
+        }
+    }
+    writeln!(writer, "SCALARS vorticity float 1\nLOOKUP_TABLE default")?;
+    for j in 0..ny {
+        for i in 0..nx {
+            writeln!(writer, "{}", vorticity[[i, j]])?;
+        }
+    }
+    writeln!(writer, "SCALARS obstacle unsigned_char 1\nLOOKUP_TABLE default")?;
+    for j in 0..ny {
+        for i in 0..nx {
+            writeln!(writer, "{}", obstacle[[i, j]] as u8)?;
+        }
+    }
+    writer.flush()
+}
+
+// XML .vti, the arrays are inline base64 little-endian binary preceded by their byte count
+pub fn write_vti(fluid_sim: &LBFluidSim, path: &Path) -> std::io::Result<()> {
+    let grid_stats = &fluid_sim.obstacle_board.grid_stats;
+    let (nx, ny) = fluid_sim.shape;
+    let (ox, oy) = origin(grid_stats);
+    let spacing = grid_stats.spacing;
+    let extent = format!("0 {} 0 {} 0 0", nx - 1, ny - 1);
+    let density = density_field(fluid_sim);
+    let velocity = velocity_field(fluid_sim);
+    let vorticity = vorticity(fluid_sim);
+    let obstacle = obstacle_mask(fluid_sim);
+
+    let mut density_bytes = Vec::with_capacity(4 * nx * ny);
+    let mut velocity_bytes = Vec::with_capacity(12 * nx * ny);
+    let mut vorticity_bytes = Vec::with_capacity(4 * nx * ny);
+    let mut obstacle_bytes = Vec::with_capacity(nx * ny);
+    for j in 0..ny {
+        for i in 0..nx {
+            density_bytes.extend(density[[i, j]].to_le_bytes());
+            for component in [velocity[[i, j]].0, velocity[[i, j]].1, 0.] {
+                velocity_bytes.extend(component.to_le_bytes());
+            }
+            vorticity_bytes.extend(vorticity[[i, j]].to_le_bytes());
+            obstacle_bytes.push(obstacle[[i, j]] as u8);
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "<?xml version=\"1.0\"?>")?;
+    writeln!(
+        writer,
+        "<VTKFile type=\"ImageData\" version=\"1.0\" byte_order=\"LittleEndian\" \
+         header_type=\"UInt32\">"
+    )?;
+    writeln!(
+        writer,
+        "  <ImageData WholeExtent=\"{}\" Origin=\"{} {} 0\" Spacing=\"{} {} {}\">",
+        extent, ox, oy, spacing, spacing, spacing
+    )?;
+    writeln!(writer, "    <Piece Extent=\"{}\">", extent)?;
+    writeln!(writer, "      <PointData Scalars=\"density\" Vectors=\"velocity\">")?;
+    write_data_array(&mut writer, "Float32", "density", 1, &density_bytes)?;
+    write_data_array(&mut writer, "Float32", "velocity", 3, &velocity_bytes)?;
+    write_data_array(&mut writer, "Float32", "vorticity", 1, &vorticity_bytes)?;
+    write_data_array(&mut writer, "UInt8", "obstacle", 1, &obstacle_bytes)?;
+    writeln!(writer, "      </PointData>")?;
+    writeln!(writer, "    </Piece>")?;
+    writeln!(writer, "  </ImageData>")?;
+    writeln!(writer, "</VTKFile>")?;
+    writer.flush()
+}
+
+fn write_data_array(
+    writer: &mut impl Write,
+    data_type: &str,
+    name: &str,
+    components: usize,
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    let mut block = (bytes.len() as u32).to_le_bytes().to_vec();
+    block.extend_from_slice(bytes);
+    writeln!(
+        writer,
+        "        <DataArray type=\"{}\" Name=\"{}\" NumberOfComponents=\"{}\" format=\"binary\">",
+        data_type, name, components
+    )?;
+    writeln!(writer, "          {}", STANDARD.encode(&block))?;
+    writeln!(writer, "        </DataArray>")
+}